@@ -1,10 +1,7 @@
-use std::env;
-use std::fs::read;
+//! Bucket the crashes of a campaign: `triage [crashes/...]`.
 
 use test_serde::main_fuzz;
 
 fn main() {
-   let args: Vec<String> = env::args().collect(); 
-   let data = read(&args[0]).unwrap();
-   main_fuzz(&data);
+    std::process::exit(tree_fuzzer::triage::main(main_fuzz));
 }
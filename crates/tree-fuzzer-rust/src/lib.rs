@@ -6,30 +6,50 @@
 // #[global_allocator]
 // static GLOBAL: MiMalloc = MiMalloc;
 
-use std::{env, path::PathBuf};
+use std::env;
 
+use clap::Parser;
 use libafl::{
-    corpus::{Corpus, InMemoryCorpus, OnDiskCorpus},
-    events::{setup_restarting_mgr_std, EventConfig},
-    executors::{inprocess::InProcessExecutor, ExitKind, ShadowExecutor},
-    feedback_or,
+    corpus::{Corpus, OnDiskCorpus},
+    events::{EventConfig, Launcher},
+    executors::{
+        inprocess::{InProcessExecutor, TimeoutInProcessForkExecutor},
+        ExitKind, ShadowExecutor, TimeoutExecutor,
+    },
+    feedback_and_fast, feedback_or,
     feedbacks::{CrashFeedback, MaxMapFeedback, TimeFeedback},
     fuzzer::{Fuzzer, StdFuzzer},
     inputs::{BytesInput, HasTargetBytes},
     monitors::MultiMonitor,
     mutators::{
-        scheduled::{havoc_mutations, StdScheduledMutator},
+        scheduled::{havoc_mutations, tokens_mutations, StdScheduledMutator},
         token_mutations::I2SRandReplace,
+        Tokens,
     },
     observers::TimeObserver,
     schedulers::{IndexesLenTimeMinimizerScheduler, QueueScheduler},
-    stages::{ShadowTracingStage, StdMutationalStage},
-    state::{HasCorpus, StdState},
+    stages::{IfStage, ShadowTracingStage, StdMutationalStage},
+    state::{HasCorpus, HasMetadata, StdState},
     Error,
 };
-use libafl_bolts::{current_nanos, rands::StdRand, tuples::tuple_list, AsSlice};
+use libafl_bolts::{
+    core_affinity::Cores,
+    rands::StdRand,
+    shmem::{ShMemProvider, StdShMemProvider},
+    tuples::{tuple_list, Merge},
+    AsSlice,
+};
 use libafl_targets::{
-    libfuzzer_initialize, libfuzzer_test_one_input, counters_maps_observer, CmpLogObserver,
+    counters_maps_observer, extra_counters, libfuzzer_initialize, libfuzzer_test_one_input,
+    CmpLogObserver,
+};
+use tree_fuzzer::{
+    args::Args,
+    fork::{ForkMirror, InProcessOrFork},
+    hangs::HangFeedback,
+    queue::{self, QueueCorpus},
+    reject::{record_result, RejectFeedback},
+    tokens,
 };
 
 #[no_mangle]
@@ -43,132 +63,187 @@ pub extern "C" fn libafl_main() {
         env::current_dir().unwrap().to_string_lossy().to_string()
     );
     env_logger::init();
-    fuzz(
-        &[PathBuf::from("./corpus")],
-        PathBuf::from("./crashes"),
-        1337,
-    )
-    .expect("An error occurred while fuzzing");
+    let args = Args::parse();
+    fuzz(&args).expect("An error occurred while fuzzing");
 }
 
 /// The actual fuzzer
-fn fuzz(corpus_dirs: &[PathBuf], objective_dir: PathBuf, broker_port: u16) -> Result<(), Error> {
+fn fuzz(args: &Args) -> Result<(), Error> {
+    let corpus_dirs = args.corpus_dirs.as_slice();
+    let cores = Cores::from_cmdline(&args.cores)?;
     // 'While the stats are state, they are usually used in the broker - which is likely never restarted
     let monitor = MultiMonitor::new(|s| println!("Monitor: {s}"));
+    // Printed so that the run can be repeated with `--seed`
+    println!("Seed: {}", args.seed);
+
+    let shmem_provider = StdShMemProvider::new()?;
+
+    // Every client gets its own state and a restarting event manager attached to the broker
+    let mut run_client = |state: Option<_>, mut restarting_mgr, core_id| {
+        // With `--queue` the corpus lives on disk, and a fresh client resumes from it
+        let queue_dir = queue::queue_dir(args.queue.as_deref(), core_id);
+        let initial_dirs = queue::initial_dirs(queue_dir.as_deref(), corpus_dirs)?;
+
+        // Create an observation channel using the coverage map
+        // We don't use the hitcounts (see the Cargo.toml, we use pcguard_edges)
+        let edges_observer = unsafe { counters_maps_observer("edges") };
+
+        // With `--fork` the target runs in a child, which hands its coverage back through shared memory
+        let mut fork_shmem = StdShMemProvider::new()?;
+        let mirror = if args.fork {
+            ForkMirror::new(&mut fork_shmem, unsafe { extra_counters() })?
+        } else {
+            ForkMirror::disabled()
+        };
 
-    // The restarting state will spawn the same process again as child, then restarted it each time it crashes.
-    let (state, mut restarting_mgr) =
-        match setup_restarting_mgr_std(monitor, broker_port, EventConfig::from_name("default")) {
-            Ok(res) => res,
-            Err(err) => match err {
-                Error::ShuttingDown => {
-                    return Ok(());
-                }
-                _ => {
-                    panic!("Failed to setup the restarter: {err}");
-                }
-            },
+        // Create an observation channel to keep track of the execution time
+        let time_observer = TimeObserver::new("time");
+
+        let cmplog_observer = CmpLogObserver::new("cmplog", true);
+
+        // Feedback to rate the interestingness of an input
+        // Inputs rejected by the fuzz target are dropped, the rest is rated by the Feedbacks in OR
+        let mut feedback = feedback_and_fast!(
+            RejectFeedback::new(),
+            feedback_or!(
+                // New maximization map feedback linked to the edges observer and the feedback state
+                MaxMapFeedback::tracking(&edges_observer, true, false),
+                // Time feedback, this one does not need a feedback state
+                TimeFeedback::with_observer(&time_observer)
+            )
+        );
+
+        // A feedback to choose if an input is a solution or not, hangs go to their own directory
+        let mut objective = feedback_or!(
+            CrashFeedback::new(),
+            HangFeedback::new(args.hangs_dir.clone())
+        );
+
+        // If not restarting, create a State from scratch
+        let mut state = state.unwrap_or_else(|| {
+            StdState::new(
+                // RNG
+                StdRand::with_seed(args.seed_for(core_id.0)),
+                // Corpus that will be evolved, in memory for performance unless `--queue` is set
+                QueueCorpus::new(queue_dir.as_deref()).unwrap(),
+                // Corpus in which we store solutions (crashes in this example),
+                // on disk so the user can get them after stopping the fuzzer
+                OnDiskCorpus::new(&args.objective_dir).unwrap(),
+                // States of the feedbacks.
+                // The feedbacks can report the data that should persist in the State.
+                &mut feedback,
+                // Same for objective feedbacks
+                &mut objective,
+            )
+            .unwrap()
+        });
+
+        println!("We're a client, let's fuzz :)");
+
+        // Keywords and punctuation of the grammar, and the short leaves of the seeds
+        if state.metadata_map().get::<Tokens>().is_none() {
+            state.add_metadata(tokens::tokens(tree_sitter_rust::language(), &initial_dirs)?);
+        }
+
+        // A minimization+queue policy to get testcasess from the corpus
+        let scheduler = IndexesLenTimeMinimizerScheduler::new(QueueScheduler::new());
+
+        // A fuzzer with feedbacks and a corpus scheduler
+        let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
+
+        // The wrapped harness function, calling out to the LLVM-style harness
+        let mut harness = |input: &BytesInput| {
+            let target = input.target_bytes();
+            let buf = target.as_slice();
+            record_result(libfuzzer_test_one_input(buf));
+            ExitKind::Ok
         };
-    println!("state restart");
-    // Create an observation channel using the coverage map
-    // We don't use the hitcounts (see the Cargo.toml, we use pcguard_edges)
-    let edges_observer = unsafe { counters_maps_observer("edges") };
-
-    // Create an observation channel to keep track of the execution time
-    let time_observer = TimeObserver::new("time");
-
-    let cmplog_observer = CmpLogObserver::new("cmplog", true);
-
-    // Feedback to rate the interestingness of an input
-    // This one is composed by two Feedbacks in OR
-    let mut feedback = feedback_or!(
-        // New maximization map feedback linked to the edges observer and the feedback state
-        MaxMapFeedback::tracking(&edges_observer, true, false),
-        // Time feedback, this one does not need a feedback state
-        TimeFeedback::with_observer(&time_observer)
-    );
 
-    // A feedback to choose if an input is a solution or not
-    let mut objective = CrashFeedback::new();
-
-    // If not restarting, create a State from scratch
-    let mut state = state.unwrap_or_else(|| {
-        StdState::new(
-            // RNG
-            StdRand::with_seed(current_nanos()),
-            // Corpus that will be evolved, we keep it in memory for performance
-            InMemoryCorpus::new(),
-            // Corpus in which we store solutions (crashes in this example),
-            // on disk so the user can get them after stopping the fuzzer
-            OnDiskCorpus::new(objective_dir).unwrap(),
-            // States of the feedbacks.
-            // The feedbacks can report the data that should persist in the State.
-            &mut feedback,
-            // Same for objective feedbacks
-            &mut objective,
-        )
-        .unwrap()
-    });
-
-    println!("We're a client, let's fuzz :)");
-
-    // A minimization+queue policy to get testcasess from the corpus
-    let scheduler = IndexesLenTimeMinimizerScheduler::new(QueueScheduler::new());
-
-    // A fuzzer with feedbacks and a corpus scheduler
-    let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
-
-    // The wrapped harness function, calling out to the LLVM-style harness
-    let mut harness = |input: &BytesInput| {
-        let target = input.target_bytes();
-        let buf = target.as_slice();
-        libfuzzer_test_one_input(buf);
-        ExitKind::Ok
+        // Create the executor for an in-process function, or one forking for every input.
+        // The mirror goes first, it restores the maps before the edges observer reads them
+        let observers = tuple_list!(mirror, edges_observer, time_observer);
+        let executor = if args.fork {
+            InProcessOrFork::Fork(TimeoutInProcessForkExecutor::new(
+                &mut harness,
+                observers,
+                &mut fuzzer,
+                &mut state,
+                &mut restarting_mgr,
+                args.timeout(),
+                fork_shmem,
+            )?)
+        } else {
+            InProcessOrFork::InProcess(TimeoutExecutor::new(
+                InProcessExecutor::new(
+                    &mut harness,
+                    observers,
+                    &mut fuzzer,
+                    &mut state,
+                    &mut restarting_mgr,
+                )?,
+                args.timeout(),
+            ))
+        };
+        // A forked child takes the comparisons along, `ForkMirror` only brings the coverage back
+        let cmplog = !args.fork;
+        let mut executor = ShadowExecutor::new(executor, tuple_list!(cmplog_observer));
+
+        // The actual target run starts here.
+        // Call LLVMFUzzerInitialize() if present.
+        let args: Vec<String> = env::args().collect();
+        if libfuzzer_initialize(&args) == -1 {
+            println!("Warning: LLVMFuzzerInitialize failed with -1");
+        }
+
+        // In case the corpus is empty (on first run), reset
+        if state.must_load_initial_inputs() {
+            state
+                .load_initial_inputs(
+                    &mut fuzzer,
+                    &mut executor,
+                    &mut restarting_mgr,
+                    &initial_dirs,
+                )
+                .unwrap_or_else(|_| panic!("Failed to load initial corpus at {initial_dirs:?}"));
+            println!("We imported {} inputs from disk.", state.corpus().count());
+        }
+
+        // Setup a tracing stage in which we log comparisons
+        let tracing = ShadowTracingStage::new(&mut executor);
+
+        // Setup a randomic Input2State stage
+        let i2s =
+            StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(I2SRandReplace::new())));
+
+        // Both only without `--fork`, they would see no comparisons
+        let cmplog_stages = IfStage::new(|_, _, _, _, _| Ok(cmplog), tuple_list!(tracing, i2s));
+
+        // Setup a basic mutator
+        let mutator = StdScheduledMutator::new(havoc_mutations().merge(tokens_mutations()));
+        let mutational = StdMutationalStage::new(mutator);
+
+        // The order of the stages matter!
+        let mut stages = tuple_list!(cmplog_stages, mutational);
+        println!("To fuzz_loop");
+
+        fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut restarting_mgr)?;
+
+        // Never reached
+        Ok(())
     };
 
-    // Create the executor for an in-process function with just one observer for edge coverage
-    let mut executor = ShadowExecutor::new(
-        InProcessExecutor::new(
-            &mut harness,
-            tuple_list!(edges_observer, time_observer),
-            &mut fuzzer,
-            &mut state,
-            &mut restarting_mgr,
-        )?,
-        tuple_list!(cmplog_observer),
-    );
-
-    // The actual target run starts here.
-    // Call LLVMFUzzerInitialize() if present.
-    let args: Vec<String> = env::args().collect();
-    if libfuzzer_initialize(&args) == -1 {
-        println!("Warning: LLVMFuzzerInitialize failed with -1");
+    match Launcher::builder()
+        .shmem_provider(shmem_provider)
+        .configuration(EventConfig::from_name("default"))
+        .monitor(monitor)
+        .run_client(&mut run_client)
+        .cores(&cores)
+        .broker_port(args.broker_port)
+        .stdout_file(args.stdout.as_deref())
+        .build()
+        .launch()
+    {
+        Err(Error::ShuttingDown) => Ok(()),
+        res => res,
     }
-
-    // In case the corpus is empty (on first run), reset
-    if state.must_load_initial_inputs() {
-        state
-            .load_initial_inputs(&mut fuzzer, &mut executor, &mut restarting_mgr, corpus_dirs)
-            .unwrap_or_else(|_| panic!("Failed to load initial corpus at {corpus_dirs:?}"));
-        println!("We imported {} inputs from disk.", state.corpus().count());
-    }
-
-    // Setup a tracing stage in which we log comparisons
-    let tracing = ShadowTracingStage::new(&mut executor);
-
-    // Setup a randomic Input2State stage
-    let i2s = StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(I2SRandReplace::new())));
-
-    // Setup a basic mutator
-    let mutator = StdScheduledMutator::new(havoc_mutations());
-    let mutational = StdMutationalStage::new(mutator);
-
-    // The order of the stages matter!
-    let mut stages = tuple_list!(tracing, i2s, mutational);
-    println!("To fuzz_loop");
-
-    fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut restarting_mgr)?;
-
-    // Never reached
-    Ok(())
 }
@@ -0,0 +1,194 @@
+//! Command-line options shared by every `libafl_main`.
+//!
+//! Options only one kind of fuzzer understands go in `X`, so that the others do not
+//! offer them: the splicers parse a [`SplicerArgs`].
+
+use std::{fs, io, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use libafl_bolts::current_nanos;
+
+#[cfg(feature = "splicer")]
+use crate::config::SpliceConfig;
+
+#[derive(Clone, Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Args<X: clap::Args = NoArgs> {
+    /// Directories with the initial inputs
+    #[arg(short = 'i', long = "corpus", default_value = "./corpus")]
+    pub corpus_dirs: Vec<PathBuf>,
+
+    /// Directory in which crashing inputs are stored
+    #[arg(short, long, default_value = "./crashes")]
+    pub objective_dir: PathBuf,
+
+    /// Directory in which inputs that time out are stored
+    #[arg(long, default_value = "./hangs")]
+    pub hangs_dir: PathBuf,
+
+    /// Port of the LLMP broker
+    #[arg(short = 'p', long, default_value_t = 1337)]
+    pub broker_port: u16,
+
+    /// Cores to spawn clients on, e.g. `0`, `1,2-4,6` or `all`
+    #[arg(short, long, default_value = "0")]
+    pub cores: String,
+
+    /// File the clients write their output to, the terminal if not set
+    #[arg(long)]
+    pub stdout: Option<String>,
+
+    /// Keep the corpus on disk in this directory, and resume from it on the next start
+    #[arg(short, long)]
+    pub queue: Option<PathBuf>,
+
+    /// Seed of the state RNG, offset by the core of each client [default: the current time]
+    #[arg(short, long, default_value_t = current_nanos(), hide_default_value = true)]
+    pub seed: u64,
+
+    /// Timeout of a single execution in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    pub timeout: u64,
+
+    /// Run every input in a forked child, so that a crash does not restart the client
+    #[arg(long)]
+    pub fork: bool,
+
+    /// Nautilus grammar or tree-sitter `node-types.json`, overriding the built-in one
+    #[arg(short, long)]
+    pub grammar: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub extra: X,
+}
+
+/// No options beyond the shared ones.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct NoArgs {}
+
+/// The options of the splicers.
+pub type SplicerArgs = Args<SpliceArgs>;
+
+/// Options of the splicers. Of the tuning, the flags override the file.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct SpliceArgs {
+    /// Directory through which the clients of a multi-core campaign share `Branches`,
+    /// in a subdirectory per broker port that is emptied at launch
+    #[arg(long, default_value = "./branches")]
+    pub branches_dir: PathBuf,
+
+    /// Minimize this crashing input along the grammar instead of fuzzing
+    #[arg(long)]
+    pub minimize: Option<PathBuf>,
+
+    /// TOML file with the tuning of the splicers, as printed at startup
+    #[arg(long = "splice-config")]
+    pub config: Option<PathBuf>,
+
+    /// Percentage of splices that take a fragment of any kind
+    #[arg(long)]
+    pub chaos: Option<u8>,
+
+    /// Percentage of edits that delete a node
+    #[arg(long)]
+    pub deletions: Option<u8>,
+
+    /// Percentage of edits that add a sibling to a list
+    #[arg(long)]
+    pub insertions: Option<u8>,
+
+    /// Edits per mutation are drawn from `1..inter-splices`
+    #[arg(long)]
+    pub inter_splices: Option<usize>,
+
+    /// No more edits once the input has this many bytes
+    #[arg(long)]
+    pub splice_max_size: Option<usize>,
+
+    /// Reparse after this many edits, only at the end if not set
+    #[arg(long)]
+    pub reparse: Option<usize>,
+}
+
+impl<X: clap::Args> Args<X> {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+
+    /// The seed of the client on core `core`, so that clients diverge but a run can be repeated.
+    ///
+    /// Takes the index of the `CoreId`, the Nautilus fuzzer links another LibAFL.
+    pub fn seed_for(&self, core: usize) -> u64 {
+        self.seed.wrapping_add(core as u64)
+    }
+
+    /// Read `--grammar` if given, else fall back to `default`.
+    pub fn grammar_or(&self, default: &str) -> io::Result<String> {
+        match &self.grammar {
+            Some(path) => fs::read_to_string(path),
+            None => Ok(default.to_owned()),
+        }
+    }
+}
+
+impl SplicerArgs {
+    /// The `--branches-dir` of this campaign, campaigns side by side have their own ports.
+    pub fn campaign_branches_dir(&self) -> PathBuf {
+        self.extra.branches_dir.join(self.broker_port.to_string())
+    }
+
+    /// `--splice-config` or the defaults, with the flags applied and validated.
+    #[cfg(feature = "splicer")]
+    pub fn splice_config(&self) -> Result<SpliceConfig, libafl::Error> {
+        let splice = &self.extra;
+        let mut config = match &splice.config {
+            Some(path) => SpliceConfig::load(path)?,
+            None => SpliceConfig::default(),
+        };
+        config.chaos = splice.chaos.unwrap_or(config.chaos);
+        config.deletions = splice.deletions.unwrap_or(config.deletions);
+        config.insertions = splice.insertions.unwrap_or(config.insertions);
+        config.inter_splices = splice.inter_splices.unwrap_or(config.inter_splices);
+        config.max_size = splice.splice_max_size.unwrap_or(config.max_size);
+        config.reparse = splice.reparse.or(config.reparse);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_for_offsets_by_core() {
+        let args = Args::<NoArgs>::parse_from(["fuzzer", "--seed", "7"]);
+        assert_eq!(args.seed_for(0), 7);
+        assert_eq!(args.seed_for(3), 10);
+
+        let args = Args::<NoArgs>::parse_from(["fuzzer", "-s", &u64::MAX.to_string()]);
+        assert_eq!(args.seed_for(1), 0);
+    }
+
+    #[test]
+    fn seed_defaults_to_one_per_run() {
+        let args = Args::<NoArgs>::parse_from(["fuzzer"]);
+        assert_ne!(args.seed_for(0), args.seed_for(1));
+        assert_eq!(args.seed_for(1), args.seed.wrapping_add(1));
+    }
+
+    #[test]
+    fn splice_flags_only_in_splicers() {
+        assert!(Args::<NoArgs>::try_parse_from(["fuzzer", "--chaos", "3"]).is_err());
+        let args = SplicerArgs::parse_from(["fuzzer", "--chaos", "3"]);
+        assert_eq!(args.extra.chaos, Some(3));
+
+        assert!(Args::<NoArgs>::try_parse_from(["fuzzer", "--branches-dir", "b"]).is_err());
+        let args = SplicerArgs::parse_from(["fuzzer", "--branches-dir", "b", "-p", "7"]);
+        assert_eq!(args.campaign_branches_dir(), PathBuf::from("b/7"));
+
+        assert!(Args::<NoArgs>::try_parse_from(["fuzzer", "--minimize", "crash"]).is_err());
+        let args = SplicerArgs::parse_from(["fuzzer", "--minimize", "crash"]);
+        assert_eq!(args.extra.minimize, Some(PathBuf::from("crash")));
+    }
+}
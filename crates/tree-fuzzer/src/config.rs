@@ -0,0 +1,173 @@
+//! Tuning of the tree splicers, from a TOML file and the command line.
+//!
+//! A campaign is only reproducible with the values it ran with, so they are validated
+//! before the first client starts and printed with [`SpliceConfig::to_toml`], ready to
+//! be passed back with `--splice-config`.
+
+use std::{fs, path::Path};
+
+use libafl::Error;
+use serde::{Deserialize, Serialize};
+
+/// How `TreeMetaData::splice_tree` edits an input. Missing keys keep their default.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpliceConfig {
+    /// Percentage of splices that take a fragment of any kind, not one that fits.
+    pub chaos: u8,
+    /// Percentage of edits that delete a node.
+    pub deletions: u8,
+    /// Percentage of edits that add a sibling to a list.
+    pub insertions: u8,
+    /// Edits per mutation are drawn from `1..inter_splices`.
+    pub inter_splices: usize,
+    /// No more edits once the input has this many bytes.
+    pub max_size: usize,
+    /// Reparse after this many edits, so that the next ones see the new nodes.
+    /// Only the finished input is rendered if not set.
+    pub reparse: Option<usize>,
+}
+
+impl Default for SpliceConfig {
+    fn default() -> Self {
+        Self {
+            chaos: 5,
+            deletions: 5,
+            insertions: 10,
+            inter_splices: 16,
+            max_size: 4000,
+            reparse: None,
+        }
+    }
+}
+
+impl SpliceConfig {
+    /// Read a TOML file of some or all of the fields.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| {
+            Error::illegal_argument(format!("Invalid splice config {}: {e}", path.display()))
+        })
+    }
+
+    /// Reject values that would panic or make no sense once fuzzing.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |msg: &str| {
+            Err(Error::illegal_argument(format!(
+                "Invalid splice config: {msg}"
+            )))
+        };
+        if self.chaos > 100 {
+            return invalid("`chaos` is a percentage, at most 100");
+        }
+        if u16::from(self.deletions) + u16::from(self.insertions) > 100 {
+            return invalid("`deletions` and `insertions` are percentages, at most 100 together");
+        }
+        if self.inter_splices < 2 {
+            return invalid("`inter_splices` must be at least 2");
+        }
+        if self.max_size == 0 {
+            return invalid("`max_size` must not be 0");
+        }
+        if self.reparse == Some(0) {
+            return invalid("`reparse` must not be 0");
+        }
+        Ok(())
+    }
+
+    /// The config as a file for `--splice-config`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Failed to serialize the splice config")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(SpliceConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let invalid = [
+            SpliceConfig {
+                chaos: 101,
+                ..SpliceConfig::default()
+            },
+            SpliceConfig {
+                deletions: 60,
+                insertions: 41,
+                ..SpliceConfig::default()
+            },
+            SpliceConfig {
+                inter_splices: 1,
+                ..SpliceConfig::default()
+            },
+            SpliceConfig {
+                max_size: 0,
+                ..SpliceConfig::default()
+            },
+            SpliceConfig {
+                reparse: Some(0),
+                ..SpliceConfig::default()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{config:?}");
+        }
+        let edge = SpliceConfig {
+            chaos: 100,
+            deletions: 60,
+            insertions: 40,
+            inter_splices: 2,
+            max_size: 1,
+            reparse: Some(1),
+        };
+        assert!(edge.validate().is_ok());
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let config = SpliceConfig {
+            chaos: 1,
+            deletions: 2,
+            insertions: 3,
+            inter_splices: 4,
+            max_size: 5,
+            reparse: Some(6),
+        };
+        let printed = config.to_toml();
+        assert_eq!(toml::from_str::<SpliceConfig>(&printed).unwrap(), config);
+
+        let default = SpliceConfig::default();
+        let printed = default.to_toml();
+        assert_eq!(toml::from_str::<SpliceConfig>(&printed).unwrap(), default);
+    }
+
+    #[test]
+    fn load_keeps_defaults_and_rejects_unknown_keys() {
+        let dir = std::env::temp_dir().join(format!("splice-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let partial = dir.join("partial.toml");
+        fs::write(&partial, "chaos = 50\nreparse = 3\n").unwrap();
+        let config = SpliceConfig::load(&partial).unwrap();
+        assert_eq!(
+            config,
+            SpliceConfig {
+                chaos: 50,
+                reparse: Some(3),
+                ..SpliceConfig::default()
+            }
+        );
+
+        let unknown = dir.join("unknown.toml");
+        fs::write(&unknown, "chaoss = 50\n").unwrap();
+        assert!(SpliceConfig::load(&unknown).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
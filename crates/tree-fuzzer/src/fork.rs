@@ -0,0 +1,206 @@
+//! Run every input in a forked child with `--fork`.
+//!
+//! A crash then only takes the child down, so the client keeps its state instead of
+//! being restarted, and a target that leaks global state (rustc) starts clean every
+//! time. Whatever the target writes dies with the child though: [`ForkMirror`] copies
+//! the coverage maps and the [`record_result`](crate::reject::record_result) flag back
+//! to the parent through shared memory. Comparisons logged for cmplog are not copied,
+//! so the fuzzers leave out their tracing and input-to-state stages in this mode.
+
+use core::fmt::{self, Debug};
+
+use libafl::{
+    executors::{Executor, ExitKind, HasObservers},
+    inputs::UsesInput,
+    observers::{Observer, UsesObservers},
+    state::UsesState,
+    Error,
+};
+use libafl_bolts::{
+    ownedref::OwnedMutSlice,
+    shmem::{ShMem, ShMemProvider},
+    AsMutSlice, AsSlice, Named,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::reject;
+
+/// An observer that carries the coverage maps and the rejection flag out of a forked child.
+///
+/// Put it first in the observers of both executors, in front of the ones reading the
+/// maps: the child copies them to shared memory after the target ran, and the parent
+/// copies them back before the other observers look. In process it does nothing.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ForkMirror<SH> {
+    /// The maps one after the other, followed by a byte for the flag.
+    #[serde(skip)]
+    shmem: Option<SH>,
+    /// The maps the instrumentation writes to.
+    #[serde(skip)]
+    maps: Vec<OwnedMutSlice<'static, u8>>,
+}
+
+impl<SH> ForkMirror<SH>
+where
+    SH: ShMem,
+{
+    /// Mirror `maps`, e.g. `vec![edges_map_mut_slice()]` or `extra_counters()`.
+    pub fn new<SP>(provider: &mut SP, maps: Vec<OwnedMutSlice<'static, u8>>) -> Result<Self, Error>
+    where
+        SP: ShMemProvider<ShMem = SH>,
+    {
+        let len = maps.iter().map(|m| m.as_slice().len()).sum::<usize>() + 1;
+        Ok(Self {
+            shmem: Some(provider.new_shmem(len)?),
+            maps,
+        })
+    }
+
+    /// A mirror for the in-process executor, which needs none.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            shmem: None,
+            maps: Vec::new(),
+        }
+    }
+}
+
+impl<SH> Debug for ForkMirror<SH> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ForkMirror {{ maps: {} }}", self.maps.len())
+    }
+}
+
+impl<SH> Named for ForkMirror<SH> {
+    fn name(&self) -> &str {
+        "ForkMirror"
+    }
+}
+
+impl<S, SH> Observer<S> for ForkMirror<SH>
+where
+    S: UsesInput,
+    SH: ShMem,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        // A child that crashes never gets to write, it must not leave the last run behind
+        if let Some(shmem) = &mut self.shmem {
+            shmem.as_mut_slice().fill(0);
+        }
+        Ok(())
+    }
+
+    fn post_exec_child(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        _exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        let Some(shmem) = &mut self.shmem else {
+            return Ok(());
+        };
+        let shared = shmem.as_mut_slice();
+        let mut offset = 0;
+        for map in &self.maps {
+            let map = map.as_slice();
+            shared[offset..offset + map.len()].copy_from_slice(map);
+            offset += map.len();
+        }
+        shared[offset] = u8::from(reject::rejected());
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        _exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        let Some(shmem) = &self.shmem else {
+            return Ok(());
+        };
+        let shared = shmem.as_slice();
+        let mut offset = 0;
+        for map in &mut self.maps {
+            let map = map.as_mut_slice();
+            map.copy_from_slice(&shared[offset..offset + map.len()]);
+            offset += map.len();
+        }
+        reject::set_rejected(shared[offset] != 0);
+        Ok(())
+    }
+}
+
+/// Either executor, chosen at runtime, so that a fuzzer is written once for both modes.
+///
+/// Both have to observe the same tuple, e.g. `tuple_list!(mirror, edges_observer, time_observer)`.
+pub enum InProcessOrFork<A, B> {
+    InProcess(A),
+    Fork(B),
+}
+
+impl<A, B> UsesState for InProcessOrFork<A, B>
+where
+    A: UsesState,
+    B: UsesState<State = A::State>,
+{
+    type State = A::State;
+}
+
+impl<A, B> UsesObservers for InProcessOrFork<A, B>
+where
+    A: UsesObservers,
+    B: UsesObservers<State = A::State, Observers = A::Observers>,
+{
+    type Observers = A::Observers;
+}
+
+impl<A, B> HasObservers for InProcessOrFork<A, B>
+where
+    A: HasObservers,
+    B: HasObservers<State = A::State, Observers = A::Observers>,
+{
+    fn observers(&self) -> &Self::Observers {
+        match self {
+            Self::InProcess(executor) => executor.observers(),
+            Self::Fork(executor) => executor.observers(),
+        }
+    }
+
+    fn observers_mut(&mut self) -> &mut Self::Observers {
+        match self {
+            Self::InProcess(executor) => executor.observers_mut(),
+            Self::Fork(executor) => executor.observers_mut(),
+        }
+    }
+}
+
+impl<A, B, EM, Z> Executor<EM, Z> for InProcessOrFork<A, B>
+where
+    A: Executor<EM, Z>,
+    B: Executor<EM, Z> + UsesState<State = A::State>,
+    EM: UsesState<State = A::State>,
+    Z: UsesState<State = A::State>,
+{
+    fn run_target(
+        &mut self,
+        fuzzer: &mut Z,
+        state: &mut Self::State,
+        mgr: &mut EM,
+        input: &Self::Input,
+    ) -> Result<ExitKind, Error> {
+        match self {
+            Self::InProcess(executor) => executor.run_target(fuzzer, state, mgr, input),
+            Self::Fork(executor) => executor.run_target(fuzzer, state, mgr, input),
+        }
+    }
+
+    fn post_run_reset(&mut self) {
+        match self {
+            Self::InProcess(executor) => executor.post_run_reset(),
+            Self::Fork(executor) => executor.post_run_reset(),
+        }
+    }
+}
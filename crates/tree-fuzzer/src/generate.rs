@@ -0,0 +1,286 @@
+//! Make up text for node kinds that the corpus has no fragment of.
+//!
+//! node-types.json tells which children a kind has, but neither their order nor the
+//! anonymous tokens between them. So the parts go in a random order, separated and
+//! wrapped by tokens of the [`Language`](tree_sitter::Language), and named leaves are
+//! guessed from their name. Most attempts do not parse, [`SubtreeGenerator::fitting`]
+//! tries until one does in the place it is meant for.
+
+use std::cell::{RefCell, RefMut};
+
+use libafl::{
+    generators::Generator,
+    state::{HasMetadata, HasRand},
+    Error,
+};
+use rand::{seq::SliceRandom, Rng};
+use tree_sitter::{Node, Tree};
+
+use crate::{
+    tokens,
+    trees::{parse, with_meta, TestTree, TreeContext, TreeMetaData},
+};
+
+/// Nodes below this depth only get their required children.
+const MAX_DEPTH: usize = 4;
+
+/// Below this depth a node must be a fragment or a leaf, or the attempt is given up:
+/// kinds that require each other would recurse forever.
+const GIVE_UP_DEPTH: usize = 12;
+
+/// Attempts at a whole input before giving up.
+const TRIES: usize = 32;
+
+/// Attempts at a node before giving up, each reparses the whole input it goes in.
+const FITTING_TRIES: usize = 4;
+
+/// Pairs that commonly wrap a node, used if the language has both halves.
+const BRACKETS: &[(&str, &str)] = &[
+    ("(", ")"),
+    ("[", "]"),
+    ("{", "}"),
+    ("<", ">"),
+    ("\"", "\""),
+    ("'", "'"),
+];
+
+/// Tokens that commonly go between the parts of a node.
+const SEPARATORS: &[&str] = &[",", ";", ":", "=", "."];
+
+/// Builds text for node kinds from node-types.json, see the module docs.
+pub(crate) struct SubtreeGenerator<'a, R> {
+    ctx: &'a TreeContext,
+    meta: &'a TreeMetaData,
+    rng: RefCell<&'a mut R>,
+    /// Anonymous tokens made of letters, the keywords of the language.
+    keywords: Vec<&'static str>,
+    brackets: Vec<(&'static str, &'static str)>,
+    separators: Vec<&'static str>,
+}
+
+impl<'a, R: Rng> SubtreeGenerator<'a, R> {
+    pub(crate) fn new(ctx: &'a TreeContext, meta: &'a TreeMetaData, rng: &'a mut R) -> Self {
+        let anonymous = tokens::anonymous_kinds(ctx.language());
+        let has = |t: &str| anonymous.contains(&t);
+        Self {
+            ctx,
+            meta,
+            rng: RefCell::new(rng),
+            keywords: anonymous
+                .iter()
+                .copied()
+                .filter(|t| tokens::is_keyword(t))
+                .collect(),
+            brackets: BRACKETS
+                .iter()
+                .copied()
+                .filter(|(open, close)| has(open) && has(close))
+                .collect(),
+            separators: SEPARATORS.iter().copied().filter(|s| has(s)).collect(),
+        }
+    }
+
+    fn rng(&self) -> RefMut<'_, &'a mut R> {
+        self.rng.borrow_mut()
+    }
+
+    fn chance(&self, percent: u8) -> bool {
+        self.rng().gen_range(0..100) < percent
+    }
+
+    fn choose<'t, T>(&self, items: &'t [T]) -> Option<&'t T> {
+        items.choose(&mut **self.rng())
+    }
+
+    /// Text for a node of `kind`, anonymous kinds are their own text.
+    ///
+    /// `None` if it would go deeper than [`GIVE_UP_DEPTH`].
+    fn generate(&self, kind: &str, named: bool, depth: usize) -> Option<Vec<u8>> {
+        if !named {
+            return Some(kind.as_bytes().to_vec());
+        }
+        let node_types = self.ctx.node_types();
+        let kinds = node_types.concrete_subtypes(kind);
+        let kind = *self.choose(&kinds).unwrap_or(&kind);
+        // Known text is the likelier to parse, and the deeper the more so
+        let fragments = self.meta.fragments(kind);
+        if !fragments.is_empty() && (depth >= MAX_DEPTH || self.chance(50)) {
+            return self.choose(fragments).cloned();
+        }
+        let slots = node_types.slots(kind);
+        if slots.is_empty() {
+            return Some(self.leaf(kind));
+        }
+        if depth >= GIVE_UP_DEPTH {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+        for slot in slots {
+            // Something at the top at least, an empty node is rarely new
+            let mut count =
+                usize::from(slot.required || depth == 0 || (depth < MAX_DEPTH && self.chance(50)));
+            if slot.multiple && depth < MAX_DEPTH {
+                count += self.rng().gen_range(0..2);
+            }
+            for _ in 0..count {
+                if let Some(&(kind, named)) = self.choose(&slot.types) {
+                    parts.push(self.generate(kind, named, depth + 1)?);
+                }
+            }
+        }
+        parts.shuffle(&mut **self.rng());
+
+        let mut text = Vec::new();
+        if self.chance(30) {
+            if let Some(keyword) = self.choose(&self.keywords) {
+                text.extend_from_slice(keyword.as_bytes());
+                text.push(b' ');
+            }
+        }
+        let brackets = self
+            .chance(50)
+            .then(|| self.choose(&self.brackets))
+            .flatten();
+        if let Some((open, _)) = brackets {
+            text.extend_from_slice(open.as_bytes());
+        }
+        let separator = self
+            .chance(50)
+            .then(|| self.choose(&self.separators))
+            .flatten();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                if let Some(separator) = separator {
+                    text.extend_from_slice(separator.as_bytes());
+                }
+                text.push(b' ');
+            }
+            text.extend_from_slice(part);
+        }
+        if let Some((_, close)) = brackets {
+            text.extend_from_slice(close.as_bytes());
+        }
+        Some(text)
+    }
+
+    /// A guess at a token of `kind`: many are keywords named after themselves.
+    fn leaf(&self, kind: &str) -> Vec<u8> {
+        let guesses = [kind, "0", "x", "\"a\"", "'a'", "a"];
+        self.choose(&guesses).unwrap().as_bytes().to_vec()
+    }
+
+    /// New text for `node` with which `text` still parses, unless it did not to begin with.
+    pub(crate) fn fitting(&self, text: &[u8], tree: &Tree, node: &Node) -> Option<Vec<u8>> {
+        if !node.is_named() || !self.ctx.node_types().contains(node.kind()) {
+            return None;
+        }
+        let range = node.byte_range();
+        let valid = !tree.root_node().has_error();
+        for _ in 0..FITTING_TRIES {
+            let Some(replacement) = self.generate(node.kind(), true, 0) else {
+                continue;
+            };
+            if replacement == text[range.clone()] {
+                continue;
+            }
+            if !valid {
+                return Some(replacement);
+            }
+            let mut candidate = Vec::with_capacity(text.len() - range.len() + replacement.len());
+            candidate.extend_from_slice(&text[..range.start]);
+            candidate.extend_from_slice(&replacement);
+            candidate.extend_from_slice(&text[range.end..]);
+            if std::str::from_utf8(&candidate)
+                .is_ok_and(|code| !parse(self.ctx.language(), code).root_node().has_error())
+            {
+                return Some(replacement);
+            }
+        }
+        None
+    }
+
+    /// A whole input that parses, or an empty one.
+    pub(crate) fn input(&self) -> Vec<u8> {
+        // The root is what an empty input parses to
+        let root = parse(self.ctx.language(), "").root_node().kind().to_owned();
+        for _ in 0..TRIES {
+            let Some(text) = self.generate(&root, true, 0) else {
+                continue;
+            };
+            if !text.is_empty()
+                && std::str::from_utf8(&text)
+                    .is_ok_and(|code| !parse(self.ctx.language(), code).root_node().has_error())
+            {
+                return text;
+            }
+        }
+        Vec::new()
+    }
+}
+
+/// Makes up initial inputs, for when there are none to load.
+pub struct TreeGenerator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl<'a> TreeGenerator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<S> Generator<TestTree, S> for TreeGenerator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn generate(&mut self, state: &mut S) -> Result<TestTree, Error> {
+        let input = with_meta(state, |meta, rng| {
+            SubtreeGenerator::new(self.ctx, meta, rng).input()
+        });
+        Ok(TestTree::new(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// `a` and `b` require each other, and there is nothing else.
+    const MUTUAL: &str = r#"[
+        {"type": "a", "named": true, "children": {"multiple": false, "required": true, "types": [{"type": "b", "named": true}]}},
+        {"type": "b", "named": true, "children": {"multiple": false, "required": true, "types": [{"type": "a", "named": true}]}}
+    ]"#;
+
+    #[test]
+    fn kinds_requiring_each_other_give_up() {
+        let ctx = TreeContext::new(tree_sitter_json::language(), MUTUAL);
+        let meta = TreeMetaData::new(&ctx, HashMap::new());
+        let mut rng = StdRng::seed_from_u64(0);
+        let generator = SubtreeGenerator::new(&ctx, &meta, &mut rng);
+        for _ in 0..100 {
+            assert_eq!(generator.generate("a", true, 0), None);
+        }
+    }
+
+    #[test]
+    fn inputs_parse() {
+        let ctx = TreeContext::new(tree_sitter_json::language(), tree_sitter_json::NODE_TYPES);
+        let meta = TreeMetaData::new(&ctx, HashMap::new());
+        let mut rng = StdRng::seed_from_u64(0);
+        let generator = SubtreeGenerator::new(&ctx, &meta, &mut rng);
+        for _ in 0..20 {
+            let input = generator.input();
+            assert!(!input.is_empty());
+            let code = std::str::from_utf8(&input).unwrap();
+            assert!(
+                !parse(ctx.language(), code).root_node().has_error(),
+                "{code}"
+            );
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Keep inputs that time out apart from the crashes.
+
+use std::{fs, path::PathBuf};
+
+use libafl::{
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::{HasTargetBytes, InputConverter, UsesInput},
+    observers::ObserversTuple,
+    state::State,
+    Error,
+};
+use libafl_bolts::{fs::write_file_atomic, AsSlice, Named};
+
+/// Writes the target bytes of an input, for inputs the target takes as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsTargetBytes;
+
+/// A `TimeoutFeedback` that writes hangs to a directory of their own.
+///
+/// It is never interesting itself, so hangs stay out of the objective corpus. Put
+/// it next to the `CrashFeedback` in the objective with `feedback_or!`.
+#[derive(Clone, Debug)]
+pub struct HangFeedback<C = AsTargetBytes> {
+    dir: PathBuf,
+    converter: C,
+}
+
+impl HangFeedback {
+    #[must_use]
+    pub fn new(dir: PathBuf) -> Self {
+        Self::with_converter(dir, AsTargetBytes)
+    }
+}
+
+impl<C> HangFeedback<C> {
+    /// Write the hangs as `converter` turns them into bytes, for inputs like a
+    /// `NautilusInput` that are unparsed before the run.
+    #[must_use]
+    pub fn with_converter(dir: PathBuf, converter: C) -> Self {
+        Self { dir, converter }
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self
+            .dir
+            .join(format!("{:016x}", libafl_bolts::hash_std(bytes)));
+        println!("Found a hang: {}", path.display());
+        write_file_atomic(path, bytes)
+    }
+}
+
+impl<C> Named for HangFeedback<C> {
+    fn name(&self) -> &str {
+        "HangFeedback"
+    }
+}
+
+impl<S> Feedback<S> for HangFeedback
+where
+    S: State,
+    <S as UsesInput>::Input: HasTargetBytes,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        if *exit_kind == ExitKind::Timeout {
+            self.write(input.target_bytes().as_slice())?;
+        }
+        Ok(false)
+    }
+}
+
+impl<S, C> Feedback<S> for HangFeedback<C>
+where
+    S: State,
+    C: InputConverter<From = S::Input>,
+    C::To: HasTargetBytes,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        if *exit_kind == ExitKind::Timeout {
+            let converted = self.converter.convert(input.clone())?;
+            self.write(converted.target_bytes().as_slice())?;
+        }
+        Ok(false)
+    }
+}
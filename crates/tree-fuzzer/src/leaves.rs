@@ -0,0 +1,450 @@
+//! Replace literals and identifiers with values that tend to break their parsers.
+//!
+//! Splicing only ever swaps one leaf of the corpus for another. [`TreeLeafMutator`]
+//! knows what kind of token a node is from its kind name (`number`, `integer_literal`,
+//! `string_literal`, `identifier`, ...) and picks from values made for that kind:
+//! overflowing and oddly written numbers, escapes and surrogates in strings, keywords
+//! where identifiers go. [`TreeCmpLogMutator`] puts what the target compared against
+//! into such leaves instead.
+
+use libafl::{
+    inputs::HasBytesVec,
+    mutators::{MutationResult, Mutator},
+    observers::cmp::{CmpValues, CmpValuesMetadata},
+    state::{HasMaxSize, HasMetadata, HasRand},
+    Error,
+};
+use libafl_bolts::{rands::Rand, Named};
+use rand::{seq::SliceRandom, Rng};
+use tree_sitter::Node;
+
+use crate::{
+    tokens,
+    trees::{mutate_tree, Edits, TestTree, TreeContext, TreeMetaData},
+};
+
+/// Numbers as in JSON: decimal, with a sign and an exponent.
+const NUMBERS: &[&str] = &[
+    "0",
+    "-0",
+    "-0.0",
+    "0e0",
+    "1e400",
+    "-1e400",
+    "1e-400",
+    "1E+308",
+    "4.9e-324",
+    "2.2250738585072011e-308",
+    "1.7976931348623157e308",
+    "9007199254740993",
+    "18446744073709551615",
+    "18446744073709551616",
+    "-9223372036854775808",
+    "-9223372036854775809",
+    "340282366920938463463374607431768211456",
+    "1e99999999999999999999",
+    "0.000000000000000000000000000000000000000000001",
+];
+
+/// Integer literals of languages with bases, separators and type suffixes.
+const INTEGERS: &[&str] = &[
+    "0",
+    "255u8",
+    "256u8",
+    "128i8",
+    "65536u16",
+    "4294967296u32",
+    "18446744073709551615u64",
+    "18446744073709551616",
+    "340282366920938463463374607431768211455u128",
+    "340282366920938463463374607431768211456",
+    "170141183460469231731687303715884105728i128",
+    "0xffff_ffff_ffff_ffff_ffff_ffff_ffff_ffff_f",
+    "0b1_0000_0000u8",
+    "0o777777777777777777777777777777777777777777777",
+    "1_000_000usize",
+    "0x_",
+    "1__",
+    "0u7",
+];
+
+/// Float literals of languages with type suffixes.
+const FLOATS: &[&str] = &[
+    "0.0",
+    "1e400",
+    "1e-400",
+    "1e400f32",
+    "3.5e38f32",
+    "1.7976931348623157e308f64",
+    "4.9e-324",
+    "1e99999999999999999999",
+    "1.",
+    "1e",
+    "1e+",
+    "1_e_1",
+    "0.1f16",
+];
+
+/// String contents, between the quotes of the node.
+const STRING_CONTENTS: &[&str] = &[
+    "",
+    "\\\"",
+    "\\\\",
+    "\\n\\r\\t\\0",
+    "\\u0000",
+    "\\u{0}",
+    "\\uD83D\\uDE00",
+    "\\uD800",
+    "\\uDFFF\\uD800",
+    "\\u{D800}",
+    "\\u{10FFFF}",
+    "\\u{110000}",
+    "\\u{}",
+    "\\u12",
+    "\\x7f\\x80",
+    "\\xff",
+    "\\q",
+    "\\",
+    "\\\n    ",
+    "\u{0}\u{7f}\u{feff}\u{10ffff}",
+    "😀𝔘ñ",
+];
+
+/// Char literals, with their quotes.
+const CHARS: &[&str] = &[
+    "'\\0'",
+    "'\\''",
+    "'\\x7f'",
+    "'\\x80'",
+    "'\\u{10FFFF}'",
+    "'\\u{110000}'",
+    "'\\u{D800}'",
+    "'😀'",
+    "'ab'",
+    "''",
+];
+
+/// Identifiers that are not quite ordinary ones.
+const IDENTIFIERS: &[&str] = &["_", "__", "r#_", "r#self", "r#crate", "ñ", "𝔘", "a\u{301}"];
+
+/// Lengths of generated long strings and identifiers.
+const LONG: &[usize] = &[256, 4096, 65536];
+
+/// What a node is as a token, going by its kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Leaf {
+    Number,
+    Integer,
+    Float,
+    String,
+    Char,
+    Identifier,
+}
+
+/// `text` as the part up to and with the opening quote, the contents and the rest.
+fn split_quotes(text: &str) -> (&str, &str, &str) {
+    let open = text.find(['"', '\'']).map_or(0, |i| i + 1);
+    let close = text
+        .rfind(['"', '\''])
+        .filter(|&i| i >= open)
+        .unwrap_or(text.len());
+    (&text[..open], &text[open..close], &text[close..])
+}
+
+impl Leaf {
+    fn of(node: &Node) -> Option<Self> {
+        let kind = node.kind();
+        // The parts of a string are not strings themselves
+        if !node.is_named() || kind.contains("content") || kind.contains("escape") {
+            return None;
+        }
+        if kind.contains("float") {
+            Some(Self::Float)
+        } else if kind.contains("integer") {
+            Some(Self::Integer)
+        } else if kind.contains("number") {
+            Some(Self::Number)
+        } else if kind.contains("char") {
+            Some(Self::Char)
+        } else if kind.contains("string") {
+            Some(Self::String)
+        } else if kind.contains("identifier") {
+            Some(Self::Identifier)
+        } else {
+            None
+        }
+    }
+}
+
+/// Replaces a literal or an identifier with a value chosen for its kind, see the module docs.
+pub struct TreeLeafMutator<'a> {
+    ctx: &'a TreeContext,
+    /// Keywords of the language, anonymous tokens made of letters.
+    keywords: Vec<&'static str>,
+}
+
+impl Named for TreeLeafMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeLeafMutator"
+    }
+}
+
+impl<'a> TreeLeafMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        let keywords = tokens::anonymous_kinds(ctx.language())
+            .into_iter()
+            .filter(|kind| tokens::is_keyword(kind))
+            .collect();
+        Self { ctx, keywords }
+    }
+
+    fn pick<'t>(rng: &mut impl Rng, values: &[&'t str]) -> &'t str {
+        values.choose(rng).unwrap()
+    }
+
+    fn long(rng: &mut impl Rng, unit: &str, max_len: usize) -> Option<String> {
+        let lens: Vec<_> = LONG.iter().filter(|&&len| len <= max_len).collect();
+        let len = **lens.choose(rng)?;
+        Some(unit.repeat(len / unit.len().max(1)))
+    }
+
+    /// A value for a `leaf` that reads `text` now, long ones up to `max_len`.
+    fn value(
+        &self,
+        rng: &mut impl Rng,
+        leaf: Leaf,
+        text: &[u8],
+        max_len: usize,
+    ) -> Option<Vec<u8>> {
+        let long = rng.gen_range(0..10) == 0;
+        let choice = rng.gen_range(0..4u8);
+        let value = match leaf {
+            Leaf::Number if long => Self::long(rng, "9", max_len)?,
+            Leaf::Number => Self::pick(rng, NUMBERS).to_owned(),
+            Leaf::Integer if long => Self::long(rng, "9", max_len)?,
+            Leaf::Integer => Self::pick(rng, INTEGERS).to_owned(),
+            Leaf::Float => Self::pick(rng, FLOATS).to_owned(),
+            Leaf::Char => Self::pick(rng, CHARS).to_owned(),
+            Leaf::String => {
+                // Keep the quotes of the literal, `"`, `r#"` or whatever else
+                let (open, _, close) = split_quotes(std::str::from_utf8(text).ok()?);
+                let contents = if long {
+                    Self::long(rng, "\\u0000a", max_len)?
+                } else {
+                    Self::pick(rng, STRING_CONTENTS).to_owned()
+                };
+                format!("{open}{contents}{close}")
+            }
+            Leaf::Identifier => match choice {
+                0 if !self.keywords.is_empty() => Self::pick(rng, &self.keywords).to_owned(),
+                1 if !self.keywords.is_empty() => format!("r#{}", Self::pick(rng, &self.keywords)),
+                2 if long => Self::long(rng, "a", max_len)?,
+                _ => Self::pick(rng, IDENTIFIERS).to_owned(),
+            },
+        };
+        (value.as_bytes() != text).then(|| value.into_bytes())
+    }
+
+    fn replace_leaf(
+        &self,
+        meta: &TreeMetaData,
+        rng: &mut impl Rng,
+        text: &[u8],
+        tree: &tree_sitter::Tree,
+        max_len: usize,
+    ) -> Option<Edits> {
+        let leaves: Vec<_> = meta
+            .all_nodes(tree)
+            .into_iter()
+            .filter_map(|node| Leaf::of(&node).map(|leaf| (node, leaf)))
+            .collect();
+        let (node, leaf) = *leaves.choose(rng)?;
+        let value = self.value(rng, leaf, &text[node.byte_range()], max_len)?;
+        let mut edits = Edits::default();
+        edits.0.insert(node.id(), value);
+        Some(edits)
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeLeafMutator<'_>
+where
+    S: HasMetadata + HasMaxSize + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        let max_len = state.max_size().saturating_sub(input.bytes().len());
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            self.replace_leaf(meta, rng, text, tree, max_len)
+        })
+    }
+}
+
+/// An operand of a logged comparison, as it would be written in the input.
+#[derive(Clone, Debug)]
+struct Operand {
+    text: String,
+    numeric: bool,
+}
+
+impl Operand {
+    /// Both operands of `cmp`. Byte strings end at a NUL and must be UTF-8 to fit in a tree.
+    fn pair(cmp: &CmpValues) -> Option<(Self, Self)> {
+        let number = |n: u64| Self {
+            text: n.to_string(),
+            numeric: true,
+        };
+        let bytes = |b: &[u8]| {
+            let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+            std::str::from_utf8(&b[..end]).ok().map(|s| Self {
+                text: s.to_owned(),
+                numeric: false,
+            })
+        };
+        Some(match cmp {
+            CmpValues::U8((a, b)) => (number((*a).into()), number((*b).into())),
+            CmpValues::U16((a, b)) => (number((*a).into()), number((*b).into())),
+            CmpValues::U32((a, b)) => (number((*a).into()), number((*b).into())),
+            CmpValues::U64((a, b)) => (number(*a), number(*b)),
+            CmpValues::Bytes((a, b)) => (bytes(a)?, bytes(b)?),
+        })
+    }
+
+    fn fits(&self, leaf: Leaf) -> bool {
+        match leaf {
+            Leaf::Number | Leaf::Integer | Leaf::Float => self.numeric,
+            Leaf::String => !self.numeric && !self.text.is_empty(),
+            Leaf::Char => !self.numeric && self.text.chars().count() == 1,
+            Leaf::Identifier => {
+                !self.numeric
+                    && self
+                        .text
+                        .starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    && self.text.chars().all(|c| c.is_alphanumeric() || c == '_')
+            }
+        }
+    }
+
+    /// Whether the leaf that reads `text` holds this operand.
+    fn is_in(&self, leaf: Leaf, text: &str) -> bool {
+        match leaf {
+            Leaf::String | Leaf::Char => split_quotes(text).1 == self.text,
+            _ => text == self.text,
+        }
+    }
+
+    /// The text of the leaf that reads `text` with this operand in its place.
+    fn written_over(&self, leaf: Leaf, text: &str) -> String {
+        match leaf {
+            Leaf::String | Leaf::Char => {
+                let (open, _, close) = split_quotes(text);
+                let quote = close.chars().next().unwrap_or('"');
+                let escaped = self
+                    .text
+                    .replace('\\', "\\\\")
+                    .replace(quote, &format!("\\{quote}"));
+                format!("{open}{escaped}{close}")
+            }
+            _ => self.text.clone(),
+        }
+    }
+}
+
+/// Puts the operands of comparisons logged by cmplog into leaves of a fitting kind.
+///
+/// `I2SRandReplace` overwrites bytes, this replaces a whole literal or identifier, so
+/// the input still parses. Like input-to-state replacement, a leaf that reads as one
+/// operand gets the other; failing that, any leaf one of them fits gets it.
+pub struct TreeCmpLogMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeCmpLogMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeCmpLogMutator"
+    }
+}
+
+impl<'a> TreeCmpLogMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+
+    fn replace_leaf(
+        &self,
+        meta: &TreeMetaData,
+        rng: &mut impl Rng,
+        text: &[u8],
+        tree: &tree_sitter::Tree,
+        (a, b): (Operand, Operand),
+    ) -> Option<Edits> {
+        let text = std::str::from_utf8(text).ok()?;
+        let leaves: Vec<_> = meta
+            .all_nodes(tree)
+            .into_iter()
+            .filter_map(|node| Leaf::of(&node).map(|leaf| (node, leaf)))
+            .collect();
+        let mut matching = Vec::new();
+        let mut fitting = Vec::new();
+        for &(node, leaf) in &leaves {
+            let leaf_text = &text[node.byte_range()];
+            for (from, to) in [(&a, &b), (&b, &a)] {
+                if to.fits(leaf) && to.text != leaf_text {
+                    if from.is_in(leaf, leaf_text) {
+                        matching.push((node, leaf, to));
+                    } else {
+                        fitting.push((node, leaf, to));
+                    }
+                }
+            }
+        }
+        let candidates = if matching.is_empty() {
+            fitting
+        } else {
+            matching
+        };
+        let (node, leaf, operand) = *candidates.choose(rng)?;
+        let mut edits = Edits::default();
+        edits.0.insert(
+            node.id(),
+            operand
+                .written_over(leaf, &text[node.byte_range()])
+                .into_bytes(),
+        );
+        Some(edits)
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeCmpLogMutator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        let logged = state
+            .metadata_map()
+            .get::<CmpValuesMetadata>()
+            .map_or(0, |meta| meta.list.len());
+        if logged == 0 {
+            return Ok(MutationResult::Skipped);
+        }
+        let idx = state.rand_mut().below(logged as u64) as usize;
+        let Some(operands) = state
+            .metadata_map()
+            .get::<CmpValuesMetadata>()
+            .and_then(|meta| Operand::pair(&meta.list[idx]))
+        else {
+            return Ok(MutationResult::Skipped);
+        };
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            self.replace_leaf(meta, rng, text, tree, operands)
+        })
+    }
+}
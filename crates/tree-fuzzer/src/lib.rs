@@ -1,15 +1,87 @@
 // use tree_sitter::Language;
-//
-// #[derive(Clone, Debug, clap::Parser)]
-// #[command(author, version, about, long_about = None)]
-// pub struct Args {
-//     
-// }
 
 // pub fn main(language: Language, node_types_json_str: &'static str) {
 //     println!("Hello, world!");
 // }
 
+#[cfg(feature = "cli")]
+pub mod args;
+#[cfg(feature = "splicer")]
+pub mod config;
+#[cfg(feature = "libafl")]
+pub mod fork;
+#[cfg(feature = "splicer")]
+pub mod generate;
+#[cfg(feature = "libafl")]
+pub mod hangs;
+#[cfg(feature = "splicer")]
+pub mod leaves;
+#[cfg(feature = "splicer")]
+pub mod minimize;
+#[cfg(feature = "splicer")]
+pub mod node_types;
+#[cfg(feature = "libafl")]
+pub mod queue;
+#[cfg(feature = "libafl")]
+pub mod reject;
+pub mod replay;
+#[cfg(feature = "libafl")]
+pub mod schedule;
+#[cfg(all(feature = "cli", feature = "splicer"))]
+pub mod splicer;
+#[cfg(feature = "splicer")]
+pub mod sync;
+#[cfg(feature = "tokens")]
+pub mod tokens;
+#[cfg(feature = "splicer")]
+pub mod trees;
+pub mod triage;
+
+use std::sync::OnceLock;
+
+pub use arbitrary;
+
+#[doc(hidden)]
+pub static RUST_LIBFUZZER_DEBUG_PATH: OnceLock<String> = OnceLock::new();
+
+/// Called by the `main` generated by [`fuzz_target!`].
+#[doc(hidden)]
+pub fn initialize() {
+    if let Ok(path) = std::env::var("RUST_LIBFUZZER_DEBUG_PATH") {
+        let _ = RUST_LIBFUZZER_DEBUG_PATH.set(path);
+    }
+}
+
+/// Indicates whether the input should be kept in the corpus or rejected. This
+/// should be the return type of your fuzz target. See [`fuzz_target!`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corpus {
+    /// Keep the input in the corpus.
+    Keep,
+
+    /// Don't add the input to the corpus.
+    Reject,
+}
+
+impl From<()> for Corpus {
+    fn from(_: ()) -> Self {
+        Self::Keep
+    }
+}
+
+impl Corpus {
+    #[doc(hidden)]
+    /// Convert this Corpus result into the [integer codes used by
+    /// `libFuzzer`](https://llvm.org/docs/LibFuzzer.html#rejecting-unwanted-inputs).
+    /// This is -1 for reject, 0 for keep.
+    pub fn to_libfuzzer_code(self) -> i32 {
+        match self {
+            Corpus::Keep => 0,
+            Corpus::Reject => -1,
+        }
+    }
+}
+
 /// Define a fuzz target.
 ///
 /// ## Example
@@ -112,12 +184,8 @@
 #[macro_export]
 macro_rules! fuzz_target {
     (|$bytes:ident| $body:expr) => {
-        
-        fn main() {
-            unsafe {
-                libafl_main();
-            }
-        }
+        $crate::fuzz_target!(@main);
+
         const _: () = {
             /// Auto-generated function
 
@@ -129,14 +197,14 @@ macro_rules! fuzz_target {
                 // `cargo fuzz`'s use!
 
                 // `RUST_LIBFUZZER_DEBUG_PATH` is set in initialization.
-                // if let Some(path) = $crate::RUST_LIBFUZZER_DEBUG_PATH.get() {
-                //     use std::io::Write;
-                //     let mut file = std::fs::File::create(path)
-                //         .expect("failed to create `RUST_LIBFUZZER_DEBUG_PATH` file");
-                //     writeln!(&mut file, "{:?}", bytes)
-                //         .expect("failed to write to `RUST_LIBFUZZER_DEBUG_PATH` file");
-                //     return 0;
-                // }
+                if let Some(path) = $crate::RUST_LIBFUZZER_DEBUG_PATH.get() {
+                    use std::io::Write;
+                    let mut file = std::fs::File::create(path)
+                        .expect("failed to create `RUST_LIBFUZZER_DEBUG_PATH` file");
+                    writeln!(&mut file, "{:?}", bytes)
+                        .expect("failed to write to `RUST_LIBFUZZER_DEBUG_PATH` file");
+                    return 0;
+                }
 
                 __libfuzzer_sys_run(bytes);
                 0
@@ -165,63 +233,78 @@ macro_rules! fuzz_target {
         $crate::fuzz_target!(|$data| $body);
     };
 
-    // (|$data:ident: $dty:ty| $body:expr) => {
-    //     $crate::fuzz_target!(|$data: $dty| -> () { $body });
-    // };
-
-    // (|$data:ident: $dty:ty| -> $rty:ty $body:block) => {
-    //     const _: () = {
-    //         /// Auto-generated function
-    //         #[no_mangle]
-    //         pub extern "C" fn rust_fuzzer_test_input(bytes: &[u8]) -> i32 {
-    //             use $crate::arbitrary::{Arbitrary, Unstructured};
-    //
-    //             // Early exit if we don't have enough bytes for the `Arbitrary`
-    //             // implementation. This helps the fuzzer avoid exploring all the
-    //             // different not-enough-input-bytes paths inside the `Arbitrary`
-    //             // implementation. Additionally, it exits faster, letting the fuzzer
-    //             // get to longer inputs that actually lead to interesting executions
-    //             // quicker.
-    //             if bytes.len() < <$dty as Arbitrary>::size_hint(0).0 {
-    //                 return -1;
-    //             }
-    //
-    //             let mut u = Unstructured::new(bytes);
-    //             let data = <$dty as Arbitrary>::arbitrary_take_rest(u);
-    //
-    //             // When `RUST_LIBFUZZER_DEBUG_PATH` is set, write the debug
-    //             // formatting of the input to that file. This is only intended for
-    //             // `cargo fuzz`'s use!
-    //
-    //             // `RUST_LIBFUZZER_DEBUG_PATH` is set in initialization.
-    //             if let Some(path) = $crate::RUST_LIBFUZZER_DEBUG_PATH.get() {
-    //                 use std::io::Write;
-    //                 let mut file = std::fs::File::create(path)
-    //                     .expect("failed to create `RUST_LIBFUZZER_DEBUG_PATH` file");
-    //                 (match data {
-    //                     Ok(data) => writeln!(&mut file, "{:#?}", data),
-    //                     Err(err) => writeln!(&mut file, "Arbitrary Error: {}", err),
-    //                 })
-    //                 .expect("failed to write to `RUST_LIBFUZZER_DEBUG_PATH` file");
-    //                 return -1;
-    //             }
-    //
-    //             let data = match data {
-    //                 Ok(d) => d,
-    //                 Err(_) => return -1,
-    //             };
-    //
-    //             let result = ::libfuzzer_sys::Corpus::from(__libfuzzer_sys_run(data));
-    //             result.to_libfuzzer_code()
-    //         }
-    //
-    //         // See above for why this is split to a separate function.
-    //         #[inline(never)]
-    //         fn __libfuzzer_sys_run($data: $dty) -> $rty {
-    //             $body
-    //         }
-    //     };
-    // };
+    (|$data:ident: $dty:ty| $body:expr) => {
+        $crate::fuzz_target!(|$data: $dty| -> () { $body });
+    };
+
+    (|$data:ident: $dty:ty| -> $rty:ty $body:block) => {
+        $crate::fuzz_target!(@main);
+
+        const _: () = {
+            /// Auto-generated function
+            #[no_mangle]
+            pub extern "C" fn rust_fuzzer_test_input(bytes: &[u8]) -> i32 {
+                use $crate::arbitrary::{Arbitrary, Unstructured};
+
+                // Early exit if we don't have enough bytes for the `Arbitrary`
+                // implementation. This helps the fuzzer avoid exploring all the
+                // different not-enough-input-bytes paths inside the `Arbitrary`
+                // implementation. Additionally, it exits faster, letting the fuzzer
+                // get to longer inputs that actually lead to interesting executions
+                // quicker.
+                if bytes.len() < <$dty as Arbitrary>::size_hint(0).0 {
+                    return -1;
+                }
+
+                let u = Unstructured::new(bytes);
+                let data = <$dty as Arbitrary>::arbitrary_take_rest(u);
+
+                // When `RUST_LIBFUZZER_DEBUG_PATH` is set, write the debug
+                // formatting of the input to that file. This is only intended for
+                // `cargo fuzz`'s use!
+
+                // `RUST_LIBFUZZER_DEBUG_PATH` is set in initialization.
+                if let Some(path) = $crate::RUST_LIBFUZZER_DEBUG_PATH.get() {
+                    use std::io::Write;
+                    let mut file = std::fs::File::create(path)
+                        .expect("failed to create `RUST_LIBFUZZER_DEBUG_PATH` file");
+                    (match data {
+                        Ok(data) => writeln!(&mut file, "{:#?}", data),
+                        Err(err) => writeln!(&mut file, "Arbitrary Error: {}", err),
+                    })
+                    .expect("failed to write to `RUST_LIBFUZZER_DEBUG_PATH` file");
+                    return -1;
+                }
+
+                let data = match data {
+                    Ok(d) => d,
+                    Err(_) => return -1,
+                };
+
+                let result = $crate::Corpus::from(__libfuzzer_sys_run(data));
+                result.to_libfuzzer_code()
+            }
+
+            // See above for why this is split to a separate function.
+            #[inline(never)]
+            fn __libfuzzer_sys_run($data: $dty) -> $rty {
+                $body
+            }
+        };
+    };
+
+    (@main) => {
+        fn main() {
+            $crate::initialize();
+            // File or directory arguments replay inputs instead of fuzzing
+            if let Some(code) = $crate::replay::main() {
+                ::std::process::exit(code);
+            }
+            unsafe {
+                libafl_main();
+            }
+        }
+    };
 }
 
 extern "C" {
@@ -0,0 +1,193 @@
+//! Shrink a crashing input one syntax node at a time.
+//!
+//! Optional nodes (see [`NodeTypes::optional_node`](crate::node_types::NodeTypes::optional_node))
+//! are deleted and every other node is replaced by the shortest fragment of its kind
+//! in `Branches`, for as long as the fuzz target still crashes in the same
+//! [`bucket`](crate::triage::Crash::bucket), so another bug found on the way is not
+//! taken for this one. Unlike a byte-level minimizer this never leaves the grammar,
+//! so the result stays readable.
+
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use libafl::Error;
+use tree_sitter::{Node, Tree};
+
+use crate::{
+    replay::{child_command, run_with_timeout},
+    trees::{parse, TreeContext, TreeMetaData},
+    triage::Crash,
+};
+
+/// Run the fuzz target of this binary on `bytes` in a child process, through the file `scratch`.
+pub fn run_target(bytes: &[u8], scratch: &Path, timeout: Duration) -> io::Result<Crash> {
+    fs::write(scratch, bytes)?;
+    let mut cmd = child_command(scratch)?;
+    cmd.env("RUST_BACKTRACE", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let (outcome, stderr) = run_with_timeout(&mut cmd, timeout)?;
+    Ok(Crash::from_run(
+        scratch,
+        bytes.len() as u64,
+        outcome,
+        &stderr,
+        timeout,
+    ))
+}
+
+/// Nodes of `tree` level by level, so that big subtrees are tried first.
+fn nodes_by_depth(tree: &Tree) -> Vec<Node<'_>> {
+    let mut all = Vec::new();
+    let mut level = vec![tree.root_node()];
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        for node in level {
+            let mut cursor = node.walk();
+            next.extend(node.children(&mut cursor));
+            all.push(node);
+        }
+        level = next;
+    }
+    all
+}
+
+/// Smaller stand-ins for byte ranges around `node`.
+///
+/// An optional node is deleted, alone or with the separator next to it, and any node
+/// can become the shortest fragment of its kind.
+fn replacements<'m>(
+    ctx: &TreeContext,
+    meta: &'m TreeMetaData,
+    node: &Node,
+) -> Vec<(Range<usize>, &'m [u8])> {
+    let range = node.byte_range();
+    let mut candidates = Vec::with_capacity(4);
+    if ctx.node_types().optional_node(node) {
+        candidates.push((range.clone(), &[][..]));
+        if let Some(next) = node.next_sibling().filter(|n| !n.is_named()) {
+            candidates.push((range.start..next.end_byte(), &[][..]));
+        }
+        if let Some(prev) = node.prev_sibling().filter(|n| !n.is_named()) {
+            candidates.push((prev.start_byte()..range.end, &[][..]));
+        }
+    }
+    if let Some(fragment) = meta.smallest_fragment(node.kind()) {
+        candidates.push((range, fragment));
+    }
+    candidates.retain(|(range, fragment)| fragment.len() < range.len());
+    candidates
+}
+
+/// Shrink `input` while `reproduces` holds for the smaller candidates.
+///
+/// A candidate that no longer parses is only tried if `input` did not parse either.
+pub fn minimize(
+    ctx: &TreeContext,
+    meta: &TreeMetaData,
+    input: &[u8],
+    mut reproduces: impl FnMut(&[u8]) -> bool,
+) -> Vec<u8> {
+    let Ok(code) = std::str::from_utf8(input) else {
+        return input.to_vec();
+    };
+    let mut text = input.to_vec();
+    let mut tree = parse(ctx.language(), code);
+    let valid = !tree.root_node().has_error();
+    // Repeat until a full pass over the tree no longer shrinks it
+    loop {
+        let mut shrunk = false;
+        let mut idx = 0;
+        loop {
+            let candidates = {
+                let nodes = nodes_by_depth(&tree);
+                let Some(node) = nodes.get(idx) else {
+                    break;
+                };
+                replacements(ctx, meta, node)
+            };
+            let mut replaced = false;
+            for (range, fragment) in candidates {
+                let mut candidate = Vec::with_capacity(text.len() - range.len() + fragment.len());
+                candidate.extend_from_slice(&text[..range.start]);
+                candidate.extend_from_slice(fragment);
+                candidate.extend_from_slice(&text[range.end..]);
+                let Ok(code) = std::str::from_utf8(&candidate) else {
+                    continue;
+                };
+                let candidate_tree = parse(ctx.language(), code);
+                if valid && candidate_tree.root_node().has_error() {
+                    continue;
+                }
+                if reproduces(&candidate) {
+                    text = candidate;
+                    tree = candidate_tree;
+                    replaced = true;
+                    break;
+                }
+            }
+            // A replaced node makes room for another one at the same index
+            if replaced {
+                shrunk = true;
+            } else {
+                idx += 1;
+            }
+        }
+        if !shrunk {
+            return text;
+        }
+    }
+}
+
+/// Minimize the crash in `path` with the fuzz target of this binary and write it to `<path>.min`.
+///
+/// Runs longer than `timeout` are hangs, so a hang is minimized as well.
+pub fn minimize_file(
+    ctx: &TreeContext,
+    meta: &TreeMetaData,
+    path: &Path,
+    timeout: Duration,
+) -> Result<PathBuf, Error> {
+    let input = fs::read(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Dot files are skipped by replay and triage, should this one be left behind
+    let scratch = path.with_file_name(format!(".{name}.min.tmp"));
+    let expected = run_target(&input, &scratch, timeout)?;
+    if expected.outcome.is_pass() {
+        fs::remove_file(&scratch)?;
+        return Err(Error::illegal_argument(format!(
+            "{} does not crash",
+            path.display()
+        )));
+    }
+    println!(
+        "Minimizing {} ({} bytes, {}: {})",
+        path.display(),
+        input.len(),
+        expected.outcome,
+        expected.message
+    );
+
+    let bucket = expected.bucket();
+    let mut runs = 0;
+    let minimized = minimize(ctx, meta, &input, |candidate| {
+        runs += 1;
+        run_target(candidate, &scratch, timeout).is_ok_and(|crash| crash.bucket() == bucket)
+    });
+    fs::remove_file(&scratch)?;
+
+    let out = path.with_file_name(format!("{name}.min"));
+    fs::write(&out, &minimized)?;
+    println!(
+        "Minimized to {} bytes in {runs} runs: {}",
+        minimized.len(),
+        out.display()
+    );
+    Ok(out)
+}
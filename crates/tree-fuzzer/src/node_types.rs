@@ -0,0 +1,323 @@
+//! Read tree-sitter's `node-types.json`.
+//
+// Copied in part from [treeedbgen] and treereduce.
+//
+// [treeedbgen]: https://github.com/langston-barrett/treeedb/blob/1a2fae3509c76cd5a8e1004f808ea800d49d1a19/treeedbgen/src/lib.rs
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+/// node-types.json
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+struct Node {
+    #[serde(rename(deserialize = "type", serialize = "type"))]
+    ty: String,
+    named: bool,
+    #[serde(default)] // empty
+    children: Children,
+    #[serde(default)] // empty
+    fields: BTreeMap<String, Field>,
+    #[serde(default)] // empty
+    subtypes: Vec<Subtype>,
+}
+
+#[derive(Default, Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+struct Children {
+    multiple: bool,
+    required: bool,
+    types: Vec<Subtype>,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+struct Field {
+    multiple: bool,
+    required: bool,
+    types: Vec<Subtype>,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+struct Subtype {
+    #[serde(rename(deserialize = "type", serialize = "type"))]
+    ty: String,
+    named: bool,
+}
+
+/// Where children go in a node: a field, or the children without one.
+#[derive(Clone, Debug)]
+pub(crate) struct Slot<'a> {
+    pub(crate) multiple: bool,
+    pub(crate) required: bool,
+    /// Kinds that fit, and whether they are named.
+    pub(crate) types: Vec<(&'a str, bool)>,
+}
+
+impl<'a> Slot<'a> {
+    fn new(multiple: bool, required: bool, types: &'a [Subtype]) -> Self {
+        Self {
+            multiple,
+            required,
+            types: types.iter().map(|t| (t.ty.as_str(), t.named)).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FieldInfo {
+    parent_ty: String,
+    multiple: bool,
+    required: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeTypes {
+    children: HashMap<String, Children>,
+    fields: HashMap<String, BTreeMap<String, Field>>,
+    subtypes: HashMap<String, Vec<String>>,
+    reverse_fields: HashMap<String, Vec<FieldInfo>>,
+}
+
+fn subtypes(name: &str, nodes: &Vec<Node>) -> Vec<String> {
+    let mut r = vec![name.to_string()];
+    for n in nodes {
+        if n.ty == name {
+            for subty in &n.subtypes {
+                r.push(subty.ty.clone());
+                r.extend(subtypes(&subty.ty, nodes));
+            }
+        }
+    }
+    r
+}
+
+impl NodeTypes {
+    pub fn new(node_types_json_str: &str) -> Result<Self, serde_json::Error> {
+        let nodes: Vec<Node> = serde_json::from_str(node_types_json_str)?;
+        let subtypes: HashMap<_, _> = nodes
+            .iter()
+            .map(|n| (n.ty.clone(), subtypes(&n.ty, &nodes)))
+            .collect();
+        let mut reverse_fields = HashMap::new();
+
+        // For each type of node...
+        for node in &nodes {
+            // Loop through it's fields...
+            for (_field_name, field) in node.fields.iter() {
+                // And save the name of all types that the field could be.
+                for subtype in &field.types {
+                    for subsubty in subtypes.get(&subtype.ty).unwrap_or(&Vec::new()) {
+                        let entry = reverse_fields.entry(subsubty.clone());
+                        entry
+                            .and_modify(|v: &mut Vec<FieldInfo>| {
+                                v.push(FieldInfo {
+                                    parent_ty: node.ty.clone(),
+                                    multiple: field.multiple,
+                                    required: field.required,
+                                });
+                            })
+                            .or_insert_with(|| {
+                                vec![FieldInfo {
+                                    parent_ty: node.ty.clone(),
+                                    multiple: field.multiple,
+                                    required: field.required,
+                                }]
+                            });
+                    }
+                }
+            }
+        }
+        Ok(NodeTypes {
+            children: nodes
+                .iter()
+                .map(|n| (n.ty.clone(), n.children.clone()))
+                .collect(),
+            fields: nodes
+                .iter()
+                .map(|n| (n.ty.clone(), n.fields.clone()))
+                .collect(),
+            subtypes,
+            reverse_fields,
+        })
+    }
+
+    /// Defaults to `true` if the real answer can't be determined.
+    fn optional(&self, node_kind: &str, parent_kind: &str) -> bool {
+        if let Some(flds) = self.reverse_fields.get(node_kind) {
+            for fi in flds {
+                if parent_kind == fi.parent_ty && (!fi.multiple || fi.required) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Defaults to `true` if the real answer can't be determined.
+    pub fn optional_node(&self, node: &tree_sitter::Node) -> bool {
+        if let Some(p) = node.parent() {
+            self.optional(node.kind(), p.kind())
+        } else {
+            true
+        }
+    }
+
+    // TODO(#21): Also include fields, include multiple and not required
+    pub fn list_types(&self, node: &tree_sitter::Node) -> Vec<String> {
+        let mut kinds = Vec::new();
+        if let Some(children) = self.children.get(node.kind()) {
+            if children.multiple && !children.required {
+                for child in &children.types {
+                    kinds.push(child.ty.clone());
+                }
+            }
+        }
+        kinds
+    }
+
+    /// The kinds in [`list_types`](Self::list_types) and their subtypes.
+    pub(crate) fn list_kinds(&self, node: &tree_sitter::Node) -> BTreeSet<String> {
+        self.list_types(node)
+            .into_iter()
+            .flat_map(|k| self.subtypes.get(&k).cloned().unwrap_or_else(|| vec![k]))
+            .collect()
+    }
+
+    /// Kinds that may take the place of `node`: all that its field, or the other children
+    /// of its parent, can be. Just its own kind if that can't be determined.
+    pub(crate) fn compatible_kinds(&self, node: &tree_sitter::Node) -> BTreeSet<String> {
+        let mut kinds = BTreeSet::from([node.kind().to_owned()]);
+        let Some(parent) = node.parent() else {
+            return kinds;
+        };
+        let mut cursor = parent.walk();
+        let field = parent
+            .children(&mut cursor)
+            .position(|c| c.id() == node.id())
+            .and_then(|i| u32::try_from(i).ok())
+            .and_then(|i| parent.field_name_for_child(i));
+        let types = match field {
+            Some(field) => self
+                .fields
+                .get(parent.kind())
+                .and_then(|f| f.get(field))
+                .map(|f| &f.types),
+            None => self.children.get(parent.kind()).map(|c| &c.types),
+        };
+        let slot: BTreeSet<String> = types
+            .into_iter()
+            .flatten()
+            .flat_map(|t| {
+                self.subtypes
+                    .get(&t.ty)
+                    .cloned()
+                    .unwrap_or_else(|| vec![t.ty.clone()])
+            })
+            .collect();
+        // A node the slot does not take (an extra, say) says nothing about the others
+        if slot.contains(node.kind()) {
+            kinds.extend(slot);
+        }
+        kinds
+    }
+
+    pub fn subtypes(&self, kind: &String) -> &[String] {
+        self.subtypes.get(kind).expect("Invalid node kind")
+    }
+
+    /// Whether `kind` is in node-types.json at all.
+    pub(crate) fn contains(&self, kind: &str) -> bool {
+        self.children.contains_key(kind)
+    }
+
+    /// Whether `kind` only stands for other kinds, like `_expression`.
+    pub(crate) fn is_supertype(&self, kind: &str) -> bool {
+        self.subtypes.get(kind).is_some_and(|s| s.len() > 1)
+    }
+
+    /// The kinds a node of `kind` really is, `kind` itself unless it is a supertype.
+    pub(crate) fn concrete_subtypes<'a>(&'a self, kind: &'a str) -> Vec<&'a str> {
+        match self.subtypes.get(kind) {
+            Some(subtypes) if subtypes.len() > 1 => subtypes
+                .iter()
+                .map(String::as_str)
+                .filter(|s| !self.is_supertype(s))
+                .collect(),
+            _ => vec![kind],
+        }
+    }
+
+    /// The fields of `kind`, then its other children. Their order is not in node-types.json.
+    pub(crate) fn slots(&self, kind: &str) -> Vec<Slot<'_>> {
+        let mut slots: Vec<_> = self
+            .fields
+            .get(kind)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .map(|f| Slot::new(f.multiple, f.required, &f.types))
+            .collect();
+        if let Some(c) = self.children.get(kind).filter(|c| !c.types.is_empty()) {
+            slots.push(Slot::new(c.multiple, c.required, &c.types));
+        }
+        slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::parse;
+
+    const VALUES: [&str; 8] = [
+        "_value", "array", "false", "null", "number", "object", "string", "true",
+    ];
+
+    fn node_types() -> NodeTypes {
+        NodeTypes::new(tree_sitter_json::NODE_TYPES).unwrap()
+    }
+
+    /// The compatible kinds of the first node whose text is `text` in `code`.
+    fn compatible(code: &str, text: &str) -> Vec<String> {
+        let tree = parse(tree_sitter_json::language(), code);
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if &code[node.byte_range()] == text && node.is_named() {
+                return node_types().compatible_kinds(&node).into_iter().collect();
+            }
+            stack.extend((0..node.child_count()).rev().filter_map(|i| node.child(i)));
+        }
+        panic!("No {text:?} in {code:?}");
+    }
+
+    #[test]
+    fn compatible_kinds_of_fields_and_children() {
+        assert_eq!(compatible(r#"{"a": 1}"#, "1"), VALUES);
+        assert_eq!(compatible(r#"{"a": 1}"#, r#""a""#), ["string"]);
+        assert_eq!(compatible("[1, null]", "null"), VALUES);
+        assert_eq!(compatible(r#"{"a": 1}"#, r#""a": 1"#), ["pair"]);
+    }
+
+    #[test]
+    fn compatible_kinds_without_a_slot() {
+        // The root has no parent, a comment is an extra
+        assert_eq!(compatible("[1]", "[1]"), ["document"]);
+        assert_eq!(compatible("[1, // c\n2]", "// c"), ["comment"]);
+    }
+
+    #[test]
+    fn slots_of_fields_then_children() {
+        let node_types = node_types();
+        let pair = node_types.slots("pair");
+        assert_eq!(pair.len(), 2);
+        assert!(pair.iter().all(|s| s.required && !s.multiple));
+        assert_eq!(pair[0].types, [("string", true)]);
+        assert_eq!(pair[1].types, [("_value", true)]);
+
+        let array = node_types.slots("array");
+        assert_eq!(array.len(), 1);
+        assert!(array[0].multiple && !array[0].required);
+        assert_eq!(array[0].types, [("_value", true)]);
+
+        assert!(node_types.slots("number").is_empty());
+        assert!(node_types.slots("unknown").is_empty());
+    }
+}
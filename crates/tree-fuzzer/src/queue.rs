@@ -0,0 +1,155 @@
+//! Keep the evolving corpus on disk with `--queue`, and resume from it.
+//!
+//! Every client writes its queue to a directory of its own, `<queue>/<core id>`,
+//! with a metadata sidecar next to every input. A client starting from scratch
+//! loads its old queue instead of the initial corpus if there is one.
+
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    corpus::{CachedOnDiskCorpus, Corpus, CorpusId, InMemoryCorpus, Testcase},
+    inputs::{Input, UsesInput},
+    Error,
+};
+use libafl_bolts::core_affinity::CoreId;
+use serde::{Deserialize, Serialize};
+
+/// Inputs of the queue kept in memory, the rest is read back from disk on demand.
+const CACHE_LEN: usize = 4096;
+
+/// The corpus of a client, in memory or, with `--queue`, cached on disk.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound = "I: serde::de::DeserializeOwned")]
+pub enum QueueCorpus<I>
+where
+    I: Input,
+{
+    InMemory(InMemoryCorpus<I>),
+    OnDisk(CachedOnDiskCorpus<I>),
+}
+
+impl<I> QueueCorpus<I>
+where
+    I: Input,
+{
+    /// On disk in `dir` if given, see [`queue_dir`].
+    pub fn new(dir: Option<&Path>) -> Result<Self, Error> {
+        Ok(match dir {
+            Some(dir) => Self::OnDisk(CachedOnDiskCorpus::new(dir, CACHE_LEN)?),
+            None => Self::InMemory(InMemoryCorpus::new()),
+        })
+    }
+}
+
+/// The queue directory of the client on `core_id`.
+pub fn queue_dir(queue: Option<&Path>, core_id: CoreId) -> Option<PathBuf> {
+    queue.map(|queue| queue.join(core_id.0.to_string()))
+}
+
+/// Where to load the initial inputs from: the queue left by the last campaign, else `corpus_dirs`.
+///
+/// The inputs are loaded back into the same directory under the same names, so the
+/// lock files LibAFL left there are removed first, or every input would be duplicated.
+pub fn initial_dirs(queue_dir: Option<&Path>, corpus_dirs: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let Some(dir) = queue_dir.filter(|dir| dir.is_dir()) else {
+        return Ok(corpus_dirs.to_vec());
+    };
+    let mut resumed = false;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.ends_with(".lafl_lock") {
+            fs::remove_file(&path)?;
+        } else if !name.starts_with('.') {
+            resumed = true;
+        }
+    }
+    if resumed {
+        println!("Resuming from the queue in {}", dir.display());
+        Ok(vec![dir.to_owned()])
+    } else {
+        Ok(corpus_dirs.to_vec())
+    }
+}
+
+impl<I> UsesInput for QueueCorpus<I>
+where
+    I: Input,
+{
+    type Input = I;
+}
+
+/// Forward to the corpus in use.
+macro_rules! each {
+    ($self:ident, $corpus:ident => $e:expr) => {
+        match $self {
+            Self::InMemory($corpus) => $e,
+            Self::OnDisk($corpus) => $e,
+        }
+    };
+}
+
+impl<I> Corpus for QueueCorpus<I>
+where
+    I: Input,
+{
+    fn count(&self) -> usize {
+        each!(self, c => c.count())
+    }
+
+    fn add(&mut self, testcase: Testcase<I>) -> Result<CorpusId, Error> {
+        each!(self, c => c.add(testcase))
+    }
+
+    fn replace(&mut self, idx: CorpusId, testcase: Testcase<I>) -> Result<Testcase<I>, Error> {
+        each!(self, c => c.replace(idx, testcase))
+    }
+
+    fn remove(&mut self, id: CorpusId) -> Result<Testcase<I>, Error> {
+        each!(self, c => c.remove(id))
+    }
+
+    fn get(&self, id: CorpusId) -> Result<&RefCell<Testcase<I>>, Error> {
+        each!(self, c => c.get(id))
+    }
+
+    fn current(&self) -> &Option<CorpusId> {
+        each!(self, c => c.current())
+    }
+
+    fn current_mut(&mut self) -> &mut Option<CorpusId> {
+        each!(self, c => c.current_mut())
+    }
+
+    fn next(&self, id: CorpusId) -> Option<CorpusId> {
+        each!(self, c => c.next(id))
+    }
+
+    fn prev(&self, id: CorpusId) -> Option<CorpusId> {
+        each!(self, c => c.prev(id))
+    }
+
+    fn first(&self) -> Option<CorpusId> {
+        each!(self, c => c.first())
+    }
+
+    fn last(&self) -> Option<CorpusId> {
+        each!(self, c => c.last())
+    }
+
+    fn nth(&self, nth: usize) -> CorpusId {
+        each!(self, c => c.nth(nth))
+    }
+
+    fn load_input_into(&self, testcase: &mut Testcase<I>) -> Result<(), Error> {
+        each!(self, c => c.load_input_into(testcase))
+    }
+
+    fn store_input_from(&self, testcase: &Testcase<I>) -> Result<(), Error> {
+        each!(self, c => c.store_input_from(testcase))
+    }
+}
@@ -0,0 +1,72 @@
+//! Keep inputs rejected by the fuzz target out of the corpus.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use libafl::{
+    events::EventFirer, executors::ExitKind, feedbacks::Feedback, observers::ObserversTuple,
+    state::State, Error,
+};
+use libafl_bolts::Named;
+
+use crate::Corpus;
+
+static REJECTED: AtomicBool = AtomicBool::new(false);
+
+/// Record the return value of `LLVMFuzzerTestOneInput` for [`RejectFeedback`].
+pub fn record_result(code: i32) {
+    REJECTED.store(
+        code == Corpus::Reject.to_libfuzzer_code(),
+        Ordering::Relaxed,
+    );
+}
+
+/// The flag as set by the last [`record_result`], for [`crate::fork::ForkMirror`].
+pub(crate) fn rejected() -> bool {
+    REJECTED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_rejected(rejected: bool) {
+    REJECTED.store(rejected, Ordering::Relaxed);
+}
+
+/// A [`RejectFeedback`] is not interesting if the fuzz target returned [`Corpus::Reject`].
+///
+/// Put it in front of the other feedbacks with `feedback_and_fast!`, so that
+/// rejected inputs neither enter the corpus nor count towards their coverage.
+#[derive(Clone, Debug, Default)]
+pub struct RejectFeedback {}
+
+impl RejectFeedback {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Named for RejectFeedback {
+    fn name(&self) -> &str {
+        "RejectFeedback"
+    }
+}
+
+impl<S> Feedback<S> for RejectFeedback
+where
+    S: State,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        // Reset, an execution that times out never records its result
+        Ok(!REJECTED.swap(false, Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,226 @@
+//! Run a harness binary on saved inputs instead of fuzzing.
+//!
+//! Like a libFuzzer binary, `./harness crash-1 crashes/` runs the fuzz target once
+//! per file (directories are expanded) and reports how each run ended. Every
+//! input runs in its own child process, so an abort does not hide the rest, and
+//! a child still running after `TREE_FUZZER_TIMEOUT` milliseconds is killed as a hang.
+
+use std::{
+    env, fmt, fs,
+    io::{self, Read},
+    os::unix::process::ExitStatusExt,
+    panic,
+    path::{Path, PathBuf},
+    process::{self, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Set in the child that runs a single input.
+const CHILD_ENV: &str = "TREE_FUZZER_REPLAY_CHILD";
+
+/// Milliseconds after which a child counts as hanging.
+pub const TIMEOUT_ENV: &str = "TREE_FUZZER_TIMEOUT";
+
+/// Timeout of a child if `TREE_FUZZER_TIMEOUT` is not set, generous for debug builds.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Exit code of the child if the fuzz target panicked, the one of an uncaught panic.
+const PANIC_EXIT_CODE: i32 = 101;
+
+/// How a single run of the fuzz target ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Panic,
+    /// Still running after the timeout, and killed.
+    Timeout,
+    /// Killed by the given signal, or exited with an unexpected code.
+    Abort {
+        signal: Option<i32>,
+        code: Option<i32>,
+    },
+}
+
+impl Outcome {
+    pub fn from_status(status: ExitStatus) -> Self {
+        match status.code() {
+            Some(0) => Outcome::Pass,
+            Some(PANIC_EXIT_CODE) => Outcome::Panic,
+            code => Outcome::Abort {
+                signal: status.signal(),
+                code,
+            },
+        }
+    }
+
+    pub fn is_pass(&self) -> bool {
+        *self == Outcome::Pass
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "pass"),
+            Outcome::Panic => write!(f, "panic"),
+            Outcome::Timeout => write!(f, "timeout"),
+            Outcome::Abort {
+                signal: Some(signal),
+                ..
+            } => write!(f, "abort (signal {signal})"),
+            Outcome::Abort {
+                code: Some(code), ..
+            } => write!(f, "abort (exit code {code})"),
+            Outcome::Abort { .. } => write!(f, "abort"),
+        }
+    }
+}
+
+/// Called by the `main` generated by [`fuzz_target!`](crate::fuzz_target).
+///
+/// Returns the exit code if the arguments asked for a replay, `None` to go on fuzzing.
+#[doc(hidden)]
+pub fn main() -> Option<i32> {
+    if env::var_os(CHILD_ENV).is_some() {
+        let path = env::args_os()
+            .nth(1)
+            .expect("No input given to the replay child");
+        return Some(run_input(Path::new(&path)));
+    }
+    let paths = inputs_from_args()?;
+    Some(match replay(&paths) {
+        Ok(outcomes) if outcomes.iter().all(|(_, o, _)| o.is_pass()) => 0,
+        Ok(_) => 1,
+        Err(err) => {
+            eprintln!("Failed to replay {paths:?}: {err}");
+            1
+        }
+    })
+}
+
+/// The inputs to replay: only plain paths on the command line, no `libafl_main` options.
+fn inputs_from_args() -> Option<Vec<PathBuf>> {
+    let args: Vec<_> = env::args_os().skip(1).collect();
+    if args.is_empty() || args.iter().any(|a| a.to_string_lossy().starts_with('-')) {
+        return None;
+    }
+    Some(args.into_iter().map(PathBuf::from).collect())
+}
+
+/// Files in `paths`, with directories replaced by the files in them.
+pub fn expand_inputs(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            // Skip the metadata and lock files LibAFL keeps next to the inputs
+            entries.retain(|p| {
+                p.is_file()
+                    && !p
+                        .file_name()
+                        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+            });
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// The timeout of a child, from `TREE_FUZZER_TIMEOUT` if set.
+pub fn timeout() -> Duration {
+    env::var(TIMEOUT_ENV)
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map_or(DEFAULT_TIMEOUT, Duration::from_millis)
+}
+
+/// Run `cmd` and kill it once `timeout` has passed.
+///
+/// Returns how it ended and what it wrote to stderr, empty unless stderr is piped.
+pub fn run_with_timeout(cmd: &mut Command, timeout: Duration) -> io::Result<(Outcome, Vec<u8>)> {
+    let mut child = cmd.spawn()?;
+    // Drain stderr on the side, a chatty target would block on a full pipe
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            pipe.read_to_end(&mut buf).map(|_| buf)
+        })
+    });
+    let start = Instant::now();
+    let outcome = loop {
+        if let Some(status) = child.try_wait()? {
+            break Outcome::from_status(status);
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            break Outcome::Timeout;
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    let stderr = match stderr {
+        Some(reader) => reader.join().expect("stderr reader panicked")?,
+        None => Vec::new(),
+    };
+    Ok((outcome, stderr))
+}
+
+/// A command that runs this binary on the single input `path`.
+pub fn child_command(path: &Path) -> io::Result<Command> {
+    let mut cmd = Command::new(env::current_exe()?);
+    cmd.arg(path).env(CHILD_ENV, "1");
+    Ok(cmd)
+}
+
+/// Run every input in its own child process and print how it went.
+pub fn replay(paths: &[PathBuf]) -> io::Result<Vec<(PathBuf, Outcome, Duration)>> {
+    let files = expand_inputs(paths)?;
+    println!("Running {} inputs", files.len());
+    let mut outcomes = Vec::with_capacity(files.len());
+    for file in files {
+        println!("Running: {}", file.display());
+        let start = Instant::now();
+        let (outcome, _) = run_with_timeout(child_command(&file)?.stdin(Stdio::null()), timeout())?;
+        let elapsed = start.elapsed();
+        println!(
+            "Executed {} in {} ms: {outcome}",
+            file.display(),
+            elapsed.as_millis()
+        );
+        outcomes.push((file, outcome, elapsed));
+    }
+    let failed = outcomes.iter().filter(|(_, o, _)| !o.is_pass()).count();
+    println!("***");
+    println!("*** {} passed, {failed} failed", outcomes.len() - failed);
+    println!("*** NOTE: fuzzing was not performed, you have only");
+    println!("***       executed the target code on a fixed set of inputs.");
+    println!("***");
+    Ok(outcomes)
+}
+
+/// Run the fuzz target once on `path` in this process.
+fn run_input(path: &Path) -> i32 {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Failed to read {}: {err}", path.display());
+            return 1;
+        }
+    };
+    // Panics can't unwind through the `extern "C"` target, report them before it aborts
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        process::exit(PANIC_EXIT_CODE);
+    }));
+    unsafe {
+        crate::rust_fuzzer_test_input(&bytes);
+    }
+    0
+}
@@ -0,0 +1,389 @@
+//! Schedule mutators by how much they find, in the spirit of MOpt.
+//!
+//! `StdScheduledMutator` picks uniformly, so the only way to prefer a mutator is to list
+//! it several times. [`AdaptiveScheduledMutator`] counts for every mutator how often an
+//! input it changed made it into the corpus, and every [`PERIOD`] mutations moves the
+//! probabilities toward those that found the most per use. None drops below
+//! [`MIN_SHARE`] of an even share, as what is useless now may pay off later in the
+//! campaign. The counts live in the state as [`MutatorStats`], which
+//! [`MutatorStatsStage`] reports to the monitor.
+
+use core::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use libafl::{
+    corpus::CorpusId,
+    events::{Event, EventFirer},
+    monitors::{AggregatorOps, UserStats, UserStatsValue},
+    mutators::{ComposedByMutations, MutationResult, Mutator, MutatorsTuple},
+    stages::Stage,
+    state::{HasMetadata, HasRand, UsesState},
+    Error,
+};
+use libafl_bolts::{rands::Rand, Named};
+use serde::{Deserialize, Serialize};
+
+/// Mutations, of all mutators together, between two reweightings.
+pub const PERIOD: u64 = 5000;
+
+/// The least probability of a mutator, relative to an even share.
+pub const MIN_SHARE: f64 = 0.1;
+
+/// How often the stats are sent to the monitor.
+const STATS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Uses, finds and probability of each mutator of an [`AdaptiveScheduledMutator`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MutatorStats {
+    names: Vec<String>,
+    uses: Vec<u64>,
+    finds: Vec<u64>,
+    /// Since the last reweighting.
+    period_uses: Vec<u64>,
+    period_finds: Vec<u64>,
+    weights: Vec<f64>,
+}
+
+libafl_bolts::impl_serdeany!(MutatorStats);
+
+impl MutatorStats {
+    fn new(names: Vec<String>) -> Self {
+        let n = names.len();
+        Self {
+            names,
+            uses: vec![0; n],
+            finds: vec![0; n],
+            period_uses: vec![0; n],
+            period_finds: vec![0; n],
+            weights: vec![1.0 / n as f64; n],
+        }
+    }
+
+    /// Short names, `Splice` for `TreeSpliceMutator` and `NautilusSpliceMutator`.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|name| {
+            let name = name
+                .strip_prefix("Tree")
+                .or_else(|| name.strip_prefix("Nautilus"))
+                .unwrap_or(name);
+            name.strip_suffix("Mutator").unwrap_or(name)
+        })
+    }
+
+    /// The mutator at `draw`, a random number, by weight.
+    fn pick(&self, draw: u64) -> usize {
+        let mut point = draw as f64 / u64::MAX as f64;
+        for (i, weight) in self.weights.iter().enumerate() {
+            if point < *weight {
+                return i;
+            }
+            point -= weight;
+        }
+        self.weights.len() - 1
+    }
+
+    /// Count a run of an input changed by `applied`, which `found` something or not.
+    fn record(&mut self, applied: &[usize], found: bool) {
+        for &i in applied {
+            self.uses[i] += 1;
+            self.period_uses[i] += 1;
+            if found {
+                self.finds[i] += 1;
+                self.period_finds[i] += 1;
+            }
+        }
+        if self.period_uses.iter().sum::<u64>() >= PERIOD {
+            self.reweight();
+        }
+    }
+
+    /// Move halfway from the current weights to the finds per use of the last period.
+    ///
+    /// [`MIN_SHARE`] of every mutator's even share is set aside first, and the rest goes
+    /// by those weights, so the floor holds without renormalizing.
+    fn reweight(&mut self) {
+        let n = self.weights.len() as f64;
+        let efficiency: Vec<f64> = self
+            .period_finds
+            .iter()
+            .zip(&self.period_uses)
+            .map(|(&finds, &uses)| finds as f64 / uses.max(1) as f64)
+            .collect();
+        let total: f64 = efficiency.iter().sum();
+        if total > 0.0 {
+            for (weight, e) in self.weights.iter_mut().zip(&efficiency) {
+                *weight = MIN_SHARE / n + (1.0 - MIN_SHARE) * (*weight + e / total) / 2.0;
+            }
+        }
+        self.period_uses.iter_mut().for_each(|uses| *uses = 0);
+        self.period_finds.iter_mut().for_each(|finds| *finds = 0);
+    }
+}
+
+/// Stacks mutations like `StdScheduledMutator`, drawn by [`MutatorStats`] weights.
+pub struct AdaptiveScheduledMutator<I, MT, S> {
+    name: String,
+    /// Of `mutations`, to tell whether the [`MutatorStats`] of the state are theirs.
+    names: Vec<String>,
+    mutations: MT,
+    max_stack_pow: u64,
+    /// The mutators that changed the input in flight, credited in `post_exec`.
+    applied: Vec<usize>,
+    phantom: PhantomData<(I, S)>,
+}
+
+impl<I, MT, S> AdaptiveScheduledMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+{
+    /// Stack up to `1 << max_stack_pow` of `mutations` on an input.
+    pub fn with_max_stack_pow(mutations: MT, max_stack_pow: u64) -> Self {
+        let names: Vec<String> = mutations.names().iter().map(|s| (*s).to_owned()).collect();
+        Self {
+            name: format!("AdaptiveScheduledMutator[{}]", names.join(", ")),
+            names,
+            mutations,
+            max_stack_pow,
+            applied: Vec::new(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<I, MT, S> Named for AdaptiveScheduledMutator<I, MT, S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<I, MT, S> ComposedByMutations<I, MT, S> for AdaptiveScheduledMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+{
+    fn mutations(&self) -> &MT {
+        &self.mutations
+    }
+
+    fn mutations_mut(&mut self) -> &mut MT {
+        &mut self.mutations
+    }
+}
+
+impl<I, MT, S> Mutator<I, S> for AdaptiveScheduledMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut I,
+        stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        // Stats of another set of mutators, a restart with a new build say, do not apply
+        if state
+            .metadata_map()
+            .get::<MutatorStats>()
+            .is_none_or(|stats| stats.names != self.names)
+        {
+            state.add_metadata(MutatorStats::new(self.names.clone()));
+        }
+
+        self.applied.clear();
+        let mut result = MutationResult::Skipped;
+        let stacked = 1 << (1 + state.rand_mut().below(self.max_stack_pow));
+        for _ in 0..stacked {
+            let draw = state.rand_mut().next();
+            let idx = state.metadata::<MutatorStats>()?.pick(draw);
+            if self
+                .mutations
+                .get_and_mutate(idx.into(), state, input, stage_idx)?
+                == MutationResult::Mutated
+            {
+                result = MutationResult::Mutated;
+                if !self.applied.contains(&idx) {
+                    self.applied.push(idx);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn post_exec(
+        &mut self,
+        state: &mut S,
+        stage_idx: i32,
+        corpus_idx: Option<CorpusId>,
+    ) -> Result<(), Error> {
+        state
+            .metadata_mut::<MutatorStats>()?
+            .record(&self.applied, corpus_idx.is_some());
+        self.mutations.post_exec_all(state, stage_idx, corpus_idx)
+    }
+}
+
+/// Sends the [`MutatorStats`] to the monitor: finds per use and probability of each mutator.
+pub struct MutatorStatsStage<E, EM, Z> {
+    last_report: Option<Instant>,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, Z> MutatorStatsStage<E, EM, Z> {
+    pub fn new() -> Self {
+        Self {
+            last_report: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, Z> Default for MutatorStatsStage<E, EM, Z> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E, EM, Z> UsesState for MutatorStatsStage<E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for MutatorStatsStage<E, EM, Z>
+where
+    E: UsesState,
+    EM: EventFirer<State = E::State>,
+    Z: UsesState<State = E::State>,
+    E::State: HasMetadata,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut E::State,
+        manager: &mut EM,
+        _corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        if self
+            .last_report
+            .is_some_and(|last| last.elapsed() < STATS_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_report = Some(Instant::now());
+        let Some(stats) = state.metadata_map().get::<MutatorStats>() else {
+            return Ok(());
+        };
+        let mut events = Vec::with_capacity(2 * stats.names.len());
+        for (i, name) in stats.names().enumerate() {
+            events.push((
+                format!("{name} finds"),
+                UserStats::new(
+                    UserStatsValue::Ratio(stats.finds[i], stats.uses[i]),
+                    AggregatorOps::Sum,
+                ),
+            ));
+            events.push((
+                format!("{name} weight"),
+                UserStats::new(
+                    UserStatsValue::Percent(stats.weights[i]),
+                    AggregatorOps::Avg,
+                ),
+            ));
+        }
+        for (name, value) in events {
+            manager.fire(
+                state,
+                Event::UpdateUserStats {
+                    name,
+                    value,
+                    phantom: PhantomData,
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(n: usize) -> MutatorStats {
+        MutatorStats::new((0..n).map(|i| format!("Tree{i}Mutator")).collect())
+    }
+
+    #[test]
+    fn pick_by_weight() {
+        let mut stats = stats(3);
+        stats.weights = vec![0.5, 0.25, 0.25];
+        assert_eq!(stats.pick(0), 0);
+        assert_eq!(stats.pick(u64::MAX / 4), 0);
+        assert_eq!(stats.pick(u64::MAX / 8 * 5), 1);
+        assert_eq!(stats.pick(u64::MAX / 8 * 7), 2);
+        // Rounding must not run past the last one
+        assert_eq!(stats.pick(u64::MAX), 2);
+    }
+
+    #[test]
+    fn reweight_favours_finds_but_keeps_a_share() {
+        let mut stats = stats(4);
+        for _ in 0..100 {
+            stats.record(&[0], true);
+            stats.record(&[1], false);
+        }
+        stats.reweight();
+        let sum: f64 = stats.weights.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+        assert!(stats.weights[0] > 0.5);
+        assert!(stats.weights[1] < 0.25);
+        assert!(stats.weights.iter().all(|&w| w >= MIN_SHARE / 4.0));
+        assert_eq!(stats.uses, [100, 100, 0, 0]);
+        assert_eq!(stats.finds, [100, 0, 0, 0]);
+        assert_eq!(stats.period_uses, [0; 4]);
+    }
+
+    #[test]
+    fn reweight_never_drops_below_the_floor() {
+        let mut stats = stats(4);
+        for _ in 0..50 {
+            stats.record(&[0], true);
+            stats.record(&[1, 2, 3], false);
+            stats.reweight();
+        }
+        let sum: f64 = stats.weights.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+        assert!(stats.weights[1..]
+            .iter()
+            .all(|w| (MIN_SHARE / 4.0..0.25).contains(w)));
+    }
+
+    #[test]
+    fn reweight_without_finds_keeps_the_weights() {
+        let mut stats = stats(2);
+        stats.record(&[0, 1], false);
+        stats.reweight();
+        assert_eq!(stats.weights, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn record_reweights_every_period() {
+        let mut stats = stats(2);
+        for _ in 0..PERIOD {
+            stats.record(&[0], true);
+        }
+        assert!(stats.weights[0] > 0.5);
+        assert_eq!(stats.period_uses, [0, 0]);
+    }
+
+    #[test]
+    fn short_names() {
+        let stats = MutatorStats::new(vec![
+            "TreeSpliceMutator".to_owned(),
+            "NautilusRandomMutator".to_owned(),
+            "Other".to_owned(),
+        ]);
+        assert!(stats.names().eq(["Splice", "Random", "Other"]));
+    }
+}
@@ -0,0 +1,297 @@
+//! The campaign of a tree splicer, the same for every tree-sitter grammar.
+//!
+//! A `*-splicer` crate only defines `libafl_main` and hands [`fuzz`] its language, the
+//! `node-types.json` of it and the coverage maps its instrumentation writes to.
+
+use std::env;
+
+use libafl::{
+    corpus::{Corpus, OnDiskCorpus},
+    events::{EventConfig, Launcher},
+    executors::{
+        inprocess::{InProcessExecutor, TimeoutInProcessForkExecutor},
+        ExitKind, ShadowExecutor, TimeoutExecutor,
+    },
+    feedback_and_fast, feedback_or,
+    feedbacks::{CrashFeedback, MaxMapFeedback, TimeFeedback},
+    fuzzer::{Fuzzer, StdFuzzer},
+    inputs::HasBytesVec,
+    monitors::MultiMonitor,
+    mutators::scheduled::StdScheduledMutator,
+    observers::{MultiMapObserver, TimeObserver},
+    schedulers::{IndexesLenTimeMinimizerScheduler, QueueScheduler},
+    stages::{IfStage, ShadowTracingStage, StdMutationalStage},
+    state::{HasCorpus, HasMetadata, StdState},
+    Error,
+};
+use libafl_bolts::{
+    core_affinity::Cores,
+    ownedref::OwnedMutSlice,
+    rands::StdRand,
+    shmem::{ShMemProvider, StdShMemProvider},
+    tuples::tuple_list,
+};
+use libafl_targets::{libfuzzer_initialize, libfuzzer_test_one_input, CmpLogObserver};
+use tree_sitter::Language;
+
+use crate::{
+    args::SplicerArgs,
+    fork::{ForkMirror, InProcessOrFork},
+    generate::TreeGenerator,
+    hangs::HangFeedback,
+    leaves::{TreeCmpLogMutator, TreeLeafMutator},
+    minimize::minimize_file,
+    queue::{self, QueueCorpus},
+    reject::{record_result, RejectFeedback},
+    schedule::{AdaptiveScheduledMutator, MutatorStatsStage},
+    sync::{clear_branches_dir, BranchesSyncStage},
+    trees::{
+        TestTree, TreeContext, TreeCrossoverMutator, TreeDeleteMutator, TreeDuplicateMutator,
+        TreeFeedback, TreeHavocMutator, TreeInsertMutator, TreeMetaData, TreeMoveMutator,
+        TreeRecursionMutator, TreeSpliceMutator, TreeSwapMutator,
+    },
+};
+
+/// The coverage maps of the target, `vec![edges_map_mut_slice()]` with `sancov_pcguard_edges`
+/// or `extra_counters()` with `sancov_8bit`.
+pub type EdgeMaps = fn() -> Vec<OwnedMutSlice<'static, u8>>;
+
+/// Fuzz the target of this binary with inputs of `language`, or minimize `--minimize`.
+///
+/// `node_types` is the `NODE_TYPES` of the grammar crate, `--grammar` replaces it.
+pub fn fuzz(
+    language: Language,
+    node_types: &str,
+    edge_maps: EdgeMaps,
+    args: &SplicerArgs,
+) -> Result<(), Error> {
+    let corpus_dirs = args.corpus_dirs.as_slice();
+    let cores = Cores::from_cmdline(&args.cores)?;
+    // 'While the stats are state, they are usually used in the broker - which is likely never restarted
+    let monitor = MultiMonitor::new(|s| println!("Monitor: {s}"));
+    // Printed so that the run can be repeated with `--splice-config` and `--seed`
+    let config = args.splice_config()?;
+    println!("Splice config:\n{}", config.to_toml());
+    println!("Seed: {}", args.seed);
+    let mut context = TreeContext::new(language, &args.grammar_or(node_types)?).with_config(config);
+
+    if let Some(crash) = &args.extra.minimize {
+        let meta = TreeMetaData::load(&context, corpus_dirs)?;
+        return minimize_file(&context, &meta, crash, args.timeout()).map(|_| ());
+    }
+
+    if cores.ids.len() > 1 {
+        // Before the clients start, so that they only import each other's trees
+        let dir = args.campaign_branches_dir();
+        clear_branches_dir(&dir)?;
+        context = context.with_branches_dir(dir);
+    }
+
+    let shmem_provider = StdShMemProvider::new()?;
+
+    // Every client gets its own state and a restarting event manager attached to the broker
+    let mut run_client = |state: Option<_>, mut restarting_mgr, core_id| {
+        // With `--queue` the corpus lives on disk, and a fresh client resumes from it
+        let queue_dir = queue::queue_dir(args.queue.as_deref(), core_id);
+        let initial_dirs = queue::initial_dirs(queue_dir.as_deref(), corpus_dirs)?;
+
+        // Create an observation channel using the coverage maps
+        let edges_observer = MultiMapObserver::new("edges", edge_maps());
+
+        // With `--fork` the target runs in a child, which hands its coverage back through shared memory
+        let mut fork_shmem = StdShMemProvider::new()?;
+        let mirror = if args.fork {
+            ForkMirror::new(&mut fork_shmem, edge_maps())?
+        } else {
+            ForkMirror::disabled()
+        };
+
+        // Create an observation channel to keep track of the execution time
+        let time_observer = TimeObserver::new("time");
+
+        let cmplog_observer = CmpLogObserver::new("cmplog", true);
+
+        // Feedback to rate the interestingness of an input
+        // Inputs rejected by the fuzz target are dropped, the rest is rated by the Feedbacks in OR
+        let mut feedback = feedback_and_fast!(
+            RejectFeedback::new(),
+            feedback_or!(
+                // New maximization map feedback linked to the edges observer and the feedback state
+                MaxMapFeedback::tracking(&edges_observer, true, false),
+                // Time feedback, this one does not need a feedback state
+                TimeFeedback::with_observer(&time_observer),
+                TreeFeedback::new(&context)
+            )
+        );
+
+        // A feedback to choose if an input is a solution or not, hangs go to their own directory
+        let mut objective = feedback_or!(
+            CrashFeedback::new(),
+            HangFeedback::new(args.hangs_dir.clone())
+        );
+
+        // If not restarting, create a State from scratch
+        let mut state = state.unwrap_or_else(|| {
+            StdState::new(
+                // RNG
+                StdRand::with_seed(args.seed_for(core_id.0)),
+                // Corpus that will be evolved, in memory for performance unless `--queue` is set
+                QueueCorpus::new(queue_dir.as_deref()).unwrap(),
+                // Corpus in which we store solutions (crashes in this example),
+                // on disk so the user can get them after stopping the fuzzer
+                OnDiskCorpus::new(&args.objective_dir).unwrap(),
+                // States of the feedbacks.
+                // The feedbacks can report the data that should persist in the State.
+                &mut feedback,
+                // Same for objective feedbacks
+                &mut objective,
+            )
+            .unwrap()
+        });
+
+        println!("We're a client, let's fuzz :)");
+
+        // A minimization+queue policy to get testcasess from the corpus
+        let scheduler = IndexesLenTimeMinimizerScheduler::new(QueueScheduler::new());
+
+        // A fuzzer with feedbacks and a corpus scheduler
+        let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
+
+        // The wrapped harness function, calling out to the LLVM-style harness
+        let mut harness = |input: &TestTree| {
+            let buf = input.bytes();
+            record_result(libfuzzer_test_one_input(buf));
+            ExitKind::Ok
+        };
+
+        if state.metadata_map().get::<TreeMetaData>().is_none() {
+            state.add_metadata(TreeMetaData::load(&context, &initial_dirs)?);
+        }
+
+        println!("Corpus loaded");
+        // Create the executor for an in-process function, or one forking for every input.
+        // The mirror goes first, it restores the maps before the edges observer reads them
+        let observers = tuple_list!(mirror, edges_observer, time_observer);
+        let executor = if args.fork {
+            InProcessOrFork::Fork(TimeoutInProcessForkExecutor::new(
+                &mut harness,
+                observers,
+                &mut fuzzer,
+                &mut state,
+                &mut restarting_mgr,
+                args.timeout(),
+                fork_shmem,
+            )?)
+        } else {
+            InProcessOrFork::InProcess(TimeoutExecutor::new(
+                InProcessExecutor::new(
+                    &mut harness,
+                    observers,
+                    &mut fuzzer,
+                    &mut state,
+                    &mut restarting_mgr,
+                )?,
+                args.timeout(),
+            ))
+        };
+        // Log the comparisons of the target in a separate run.
+        // A forked child takes them along, `ForkMirror` only brings the coverage back
+        let cmplog = !args.fork;
+        let mut executor = ShadowExecutor::new(executor, tuple_list!(cmplog_observer));
+
+        // The actual target run starts here.
+        // Call LLVMFUzzerInitialize() if present.
+        let args: Vec<String> = env::args().collect();
+        if libfuzzer_initialize(&args) == -1 {
+            println!("Warning: LLVMFuzzerInitialize failed with -1");
+        }
+
+        // In case the corpus is empty (on first run), reset
+        if state.must_load_initial_inputs() {
+            state
+                .load_initial_inputs(
+                    &mut fuzzer,
+                    &mut executor,
+                    &mut restarting_mgr,
+                    &initial_dirs,
+                )
+                .unwrap_or_else(|err| {
+                    if let Error::Serialize(msg, backtrace) = err {
+                        println!("{msg} {backtrace:?}");
+                    }
+                    panic!("Failed to load initial corpus at {initial_dirs:?}");
+                });
+            println!("We imported {} inputs from disk.", state.corpus().count());
+            if state.corpus().count() == 0 {
+                // Nothing to start from, make up inputs from node-types.json
+                state.generate_initial_inputs_forced(
+                    &mut fuzzer,
+                    &mut executor,
+                    &mut TreeGenerator::new(&context),
+                    &mut restarting_mgr,
+                    8,
+                )?;
+                println!("We generated {} inputs.", state.corpus().count());
+            }
+        }
+
+        // Setup a tracing stage in which we log comparisons
+        let tracing = ShadowTracingStage::new(&mut executor);
+
+        // Setup an Input2State stage, putting the logged operands into leaves
+        let i2s = StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(
+            TreeCmpLogMutator::new(&context)
+        )));
+
+        // Both only without `--fork`, they would see no comparisons
+        let cmplog_stages = IfStage::new(|_, _, _, _, _| Ok(cmplog), tuple_list!(tracing, i2s));
+
+        // Setup a mutator that favours the mutations that find the most
+        let mutator = AdaptiveScheduledMutator::with_max_stack_pow(
+            tuple_list!(
+                TreeSpliceMutator::new(&context),
+                TreeDeleteMutator::new(&context),
+                TreeInsertMutator::new(&context),
+                TreeRecursionMutator::new(&context),
+                TreeSwapMutator::new(&context),
+                TreeDuplicateMutator::new(&context),
+                TreeMoveMutator::new(&context),
+                TreeCrossoverMutator::new(&context),
+                TreeLeafMutator::new(&context),
+                TreeHavocMutator::new(),
+            ),
+            2,
+        );
+        let mutational = StdMutationalStage::new(mutator);
+
+        // Pick up the fragments found by the other clients
+        let sync = BranchesSyncStage::new(&context);
+
+        // Report how each mutation fares to the monitor
+        let stats = MutatorStatsStage::new();
+
+        // The order of the stages matter!
+        let mut stages = tuple_list!(sync, cmplog_stages, mutational, stats);
+        println!("To fuzz_loop");
+
+        fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut restarting_mgr)?;
+
+        // Never reached
+        Ok(())
+    };
+
+    match Launcher::builder()
+        .shmem_provider(shmem_provider)
+        .configuration(EventConfig::from_name("default"))
+        .monitor(monitor)
+        .run_client(&mut run_client)
+        .cores(&cores)
+        .broker_port(args.broker_port)
+        .stdout_file(args.stdout.as_deref())
+        .build()
+        .launch()
+    {
+        Err(Error::ShuttingDown) => Ok(()),
+        res => res,
+    }
+}
@@ -0,0 +1,109 @@
+//! Share `Branches` between the clients of a multi-core campaign.
+//!
+//! Corpus entries are already synced by the event manager, but a peer only keeps
+//! (and splices from) the ones that are interesting to it. Every client therefore
+//! publishes the trees it adds to its own `Branches` in a shared directory, see
+//! [`TreeContext::with_branches_dir`], and this stage imports the ones written by the others.
+//! The directory belongs to one campaign, [`clear_branches_dir`] empties it at launch.
+
+use core::marker::PhantomData;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use libafl::{
+    corpus::CorpusId,
+    stages::Stage,
+    state::{HasMetadata, UsesState},
+    Error,
+};
+
+use crate::trees::{TestTree, TreeContext, TreeMetaData};
+
+/// How often the shared directory is scanned.
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Remove the trees an earlier campaign left in `dir`, called once before the clients start.
+pub fn clear_branches_dir(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+pub struct BranchesSyncStage<'a, E, EM, Z> {
+    ctx: &'a TreeContext,
+    seen: HashSet<OsString>,
+    last_sync: Option<Instant>,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<'a, E, EM, Z> BranchesSyncStage<'a, E, EM, Z> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self {
+            ctx,
+            seen: HashSet::new(),
+            last_sync: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, Z> UsesState for BranchesSyncStage<'_, E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for BranchesSyncStage<'_, E, EM, Z>
+where
+    E: UsesState,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+    E::State: HasMetadata,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut E::State,
+        _manager: &mut EM,
+        _corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        let Some(dir) = self.ctx.branches_dir() else {
+            return Ok(());
+        };
+        if self
+            .last_sync
+            .is_some_and(|last| last.elapsed() < SYNC_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_sync = Some(Instant::now());
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        let meta = state
+            .metadata_map_mut()
+            .get_mut::<TreeMetaData>()
+            .expect("TreeMeta not in the state");
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name.to_string_lossy().starts_with('.') || self.seen.contains(&name) {
+                continue;
+            }
+            // Our own trees come back here as well, `Branches` ignores known fragments.
+            let bytes = fs::read(entry.path())?;
+            meta.add_tree(&TestTree::new(bytes), self.ctx);
+            self.seen.insert(name);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//! A dictionary for the byte-level fuzzers, from the grammar and the seeds.
+//!
+//! The anonymous node kinds of a tree-sitter grammar are its keywords and punctuation,
+//! `impl`, `where` or `=>`, and the short leaves of the seeds are the identifiers and
+//! literals the target knows about. Both go into LibAFL's [`Tokens`] for `TokenInsert`
+//! and `TokenReplace`, which havoc alone is unlikely to come up with.
+
+use std::{fs, path::PathBuf};
+
+use libafl::{mutators::Tokens, Error};
+use tree_sitter::{Language, Parser};
+
+use crate::replay::expand_inputs;
+
+/// Leaves longer than this are rarely worth inserting elsewhere.
+const MAX_TOKEN_LEN: usize = 32;
+
+/// The anonymous node kinds of `language`, its keywords and punctuation.
+pub fn anonymous_kinds(language: Language) -> Vec<&'static str> {
+    (0..language.node_kind_count())
+        .filter_map(|id| u16::try_from(id).ok())
+        .filter(|&id| !language.node_kind_is_named(id) && language.node_kind_is_visible(id))
+        .filter_map(|id| language.node_kind_for_id(id))
+        .filter(|kind| !kind.is_empty())
+        .collect()
+}
+
+/// Whether an anonymous kind is a keyword, made of letters rather than punctuation.
+pub fn is_keyword(kind: &str) -> bool {
+    kind.chars().all(|c| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// The keywords and punctuation of `language`.
+pub fn grammar_tokens(language: Language) -> Vec<Vec<u8>> {
+    anonymous_kinds(language)
+        .into_iter()
+        .map(|kind| kind.as_bytes().to_vec())
+        .collect()
+}
+
+/// The distinct short leaves of the inputs in `corpus_dirs`.
+pub fn corpus_tokens(language: Language, corpus_dirs: &[PathBuf]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(language)
+        .expect("Failed to set tree-sitter parser language");
+    let mut tokens = Vec::new();
+    for path in expand_inputs(corpus_dirs)? {
+        let text = fs::read(&path)?;
+        let Some(tree) = parser.parse(&text, None) else {
+            continue;
+        };
+        let mut cursor = tree.walk();
+        // Depth first, visiting every node once
+        loop {
+            let node = cursor.node();
+            if node.child_count() == 0 && !node.is_error() && !node.is_missing() {
+                let leaf = &text[node.byte_range()];
+                if !leaf.is_empty() && leaf.len() <= MAX_TOKEN_LEN {
+                    tokens.push(leaf.to_vec());
+                }
+            }
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            while cursor.goto_parent() && !cursor.goto_next_sibling() {}
+            if cursor.node() == tree.root_node() {
+                break;
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// [`grammar_tokens`] and [`corpus_tokens`] as `Tokens` metadata.
+pub fn tokens(language: Language, corpus_dirs: &[PathBuf]) -> Result<Tokens, Error> {
+    let mut tokens = Tokens::new();
+    tokens.add_tokens(grammar_tokens(language));
+    tokens.add_tokens(corpus_tokens(language, corpus_dirs)?);
+    println!("Dictionary of {} tokens", tokens.len());
+    Ok(tokens)
+}
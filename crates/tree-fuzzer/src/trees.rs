@@ -0,0 +1,1755 @@
+use crate::config::SpliceConfig;
+use crate::generate::SubtreeGenerator;
+use crate::node_types::NodeTypes;
+use core::{fmt::Debug, marker::PhantomData};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use libafl::corpus::{Testcase, Corpus};
+use libafl::random_corpus_id;
+use libafl::events::{Event, EventFirer};
+use libafl::feedbacks::Feedback;
+use libafl::inputs::{HasBytesVec, HasTargetBytes, Input};
+use libafl::monitors::{AggregatorOps, UserStats, UserStatsValue};
+use libafl::observers::ObserversTuple;
+use libafl_bolts::{fs::write_file_atomic, ownedref::OwnedSlice, rands::Rand, HasLen, Named};
+use tree_sitter::{InputEdit, Language, Node, Point, Tree};
+use libafl::state::{HasCorpus, HasMaxSize, HasMetadata, HasRand, State};
+use libafl::mutators::{
+    havoc_mutations, HavocMutationsType, Mutator, MutationResult, StdScheduledMutator,
+};
+use libafl::executors::ExitKind;
+use libafl::Error;
+use rand::{seq::SliceRandom, Rng, RngCore};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use tree_sitter_edit::Editor;
+use serde::{Deserialize, Serialize};
+
+pub fn parse(language: Language, code: &str) -> Tree {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(language)
+        .expect("Failed to set tree-sitter parser language");
+    parser.parse(code, None).expect("Failed to parse code")
+}
+
+/// Parse `text`, reusing `old` if [`edit_tree`] told it about the edits that made `text`.
+fn parse_bytes(language: Language, text: &[u8], old: Option<&Tree>) -> Tree {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(language)
+        .expect("Failed to set tree-sitter parser language");
+    parser.parse(text, old).expect("Failed to parse code")
+}
+
+/// Where `inserted` ends if it starts at `start`.
+fn end_point(start: Point, inserted: &[u8]) -> Point {
+    match inserted.iter().rposition(|&b| b == b'\n') {
+        Some(last) => Point::new(
+            start.row + inserted.iter().filter(|&&b| b == b'\n').count(),
+            inserted.len() - last - 1,
+        ),
+        None => Point::new(start.row, start.column + inserted.len()),
+    }
+}
+
+/// Apply `edits` to the positions in `tree`, so that it can be reparsed incrementally.
+///
+/// Only the outermost edited nodes count, as in rendering. Their edits go from the back
+/// to the front, so the positions of those before are still those of the old text.
+fn edit_tree(tree: &mut Tree, edits: &Edits) {
+    let mut input_edits = Vec::with_capacity(edits.0.len());
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    // Depth first, not below edited nodes
+    loop {
+        let node = cursor.node();
+        let edited = edits.0.get(&node.id());
+        if let Some(replacement) = edited {
+            input_edits.push(InputEdit {
+                start_byte: node.start_byte(),
+                old_end_byte: node.end_byte(),
+                new_end_byte: node.start_byte() + replacement.len(),
+                start_position: node.start_position(),
+                old_end_position: node.end_position(),
+                new_end_position: end_point(node.start_position(), replacement),
+            });
+        }
+        if (edited.is_none() && cursor.goto_first_child()) || cursor.goto_next_sibling() {
+            continue;
+        }
+        while cursor.goto_parent() && !cursor.goto_next_sibling() {}
+        if cursor.node() == root {
+            break;
+        }
+    }
+    drop(cursor);
+    input_edits.sort_by_key(|edit| std::cmp::Reverse(edit.start_byte));
+    for edit in &input_edits {
+        tree.edit(edit);
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub(crate) struct Edits(pub(crate) HashMap<usize, Vec<u8>>);
+
+impl Editor for Edits {
+    fn has_edit(&self, _tree: &Tree, node: &Node) -> bool {
+        self.0.get(&node.id()).is_some()
+    }
+
+    fn edit(&self, _source: &[u8], tree: &Tree, node: &Node) -> Vec<u8> {
+        debug_assert!(self.has_edit(tree, node));
+        self.0.get(&node.id()).unwrap().clone()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Branches(BTreeMap<String, (Vec<Vec<u8>>, HashSet<Vec<u8>>)>);
+
+impl Branches {
+    /// Kinds and their fragments in the order they were first seen, so that a seed
+    /// picks the same ones every run.
+    fn new(trees: Vec<(Vec<u8>, Tree)>) -> Self {
+        let mut branches = Branches(BTreeMap::new());
+        for (text, tree) in &trees {
+            branches.add_tree(text, tree);
+        }
+        branches
+    }
+
+    fn add_tree(&mut self, text: &[u8], tree: &Tree) {
+        // dbg!("Adding tree");
+            let mut nodes = vec![tree.root_node()];
+            while !nodes.is_empty() {
+                // dbg!();
+                let mut children = Vec::with_capacity(nodes.len()); // guesstimate
+                for node in nodes {
+                    let slot = self.0
+                        .entry(node.kind().to_string())
+                        .or_insert_with(|| (vec![], HashSet::with_capacity(1)));
+                    let txt = text[node.byte_range()].to_vec();
+                    let contains = slot.1.contains(&txt);
+                    if !contains {
+                        slot.0.push(txt.clone());
+                        slot.1.insert(txt);
+                    }
+                    let mut i = 0;
+                    while let Some(child) = node.child(i) {
+                        children.push(child);
+                        i += 1;
+                    }
+                }
+                nodes = children;
+            }
+
+        // dbg!("Added tree");
+    } 
+
+    fn possible(&self) -> usize {
+        let mut possible_mutations = 0;
+        for s in self.0.values() {
+            possible_mutations += s.0.len() - 1;
+        }
+        possible_mutations
+    }
+}
+
+pub struct TreeContext {
+    node_types: NodeTypes,
+    language: Language,
+    config: SpliceConfig,
+    branches_dir: Option<PathBuf>,
+}
+
+impl TreeContext {
+    pub fn new(language: Language, node_types_str: &str) -> Self {
+
+        Self {
+            node_types: NodeTypes::new(node_types_str).unwrap(),
+            language,
+            config: SpliceConfig::default(),
+            branches_dir: None,
+        }
+    }
+
+    /// Splice with `config` instead of the defaults.
+    pub fn with_config(mut self, config: SpliceConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &SpliceConfig {
+        &self.config
+    }
+
+    /// Share the inputs that feed `Branches` with other clients through `dir`.
+    pub fn with_branches_dir(mut self, dir: PathBuf) -> Self {
+        self.branches_dir = Some(dir);
+        self
+    }
+
+    pub fn branches_dir(&self) -> Option<&Path> {
+        self.branches_dir.as_deref()
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    pub(crate) fn node_types(&self) -> &NodeTypes {
+        &self.node_types
+    }
+}
+
+/// The RNG of a state, for the `rand` API the tree mutations are written against.
+///
+/// All choices come from the state, so the seed of a run decides them.
+pub(crate) struct StateRng<'a, R>(pub(crate) &'a mut R);
+
+impl<R: Rand> RngCore for StateRng<'_, R> {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.0.next().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Run `f` on the `TreeMetaData` and the RNG of `state`.
+///
+/// The metadata leaves the map meanwhile, else it would keep all of `state` borrowed.
+pub(crate) fn with_meta<S, T>(
+    state: &mut S,
+    f: impl FnOnce(&mut TreeMetaData, &mut StateRng<'_, S::Rand>) -> T,
+) -> T
+where
+    S: HasMetadata + HasRand,
+{
+    let mut meta = state
+        .metadata_map_mut()
+        .remove::<TreeMetaData>()
+        .expect("TreeMeta not in the state");
+    let result = f(&mut meta, &mut StateRng(state.rand_mut()));
+    state.metadata_map_mut().insert_boxed(meta);
+    result
+}
+
+/// The syntax an input is made of: node kinds, (parent, child) kinds and (parent, field) slots.
+///
+/// Error and missing nodes, and everything below them, say nothing about the grammar
+/// and are left out.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct SyntaxCoverage {
+    kinds: HashSet<u16>,
+    edges: HashSet<(u16, u16)>,
+    fields: HashSet<(u16, u16)>,
+}
+
+impl SyntaxCoverage {
+    /// What the tree of `index` covers that `self` does not.
+    fn novelties(&self, index: &NodeIndex) -> Self {
+        let mut new = Self::default();
+        // In preorder, parents are counted or not before their children
+        let mut counted = Vec::with_capacity(index.len());
+        for i in 0..index.len() {
+            let parent = index.parents[i];
+            let count = !index.broken[i] && parent.is_none_or(|p| counted[p]);
+            counted.push(count);
+            if !count {
+                continue;
+            }
+            let kind = index.kinds[i];
+            if !self.kinds.contains(&kind) {
+                new.kinds.insert(kind);
+            }
+            let Some(parent) = parent else {
+                continue;
+            };
+            let edge = (index.kinds[parent], kind);
+            if !self.edges.contains(&edge) {
+                new.edges.insert(edge);
+            }
+            if let Some(field) = index.fields[i] {
+                if !self.fields.contains(&(index.kinds[parent], field)) {
+                    new.fields.insert((index.kinds[parent], field));
+                }
+            }
+        }
+        new
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.kinds.extend(other.kinds);
+        self.edges.extend(other.edges);
+        self.fields.extend(other.fields);
+    }
+
+    fn len(&self) -> usize {
+        self.kinds.len() + self.edges.len() + self.fields.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Keeps inputs with syntax no earlier input had, see [`SyntaxCoverage`], and feeds
+/// `Branches` with every input added to the corpus.
+pub struct TreeFeedback<'a, S> {
+    ctx: &'a TreeContext,
+    /// Found by `is_interesting`, recorded once the input makes it into the corpus.
+    novelties: Option<SyntaxCoverage>,
+    phantom: PhantomData<S>,
+}
+
+
+impl<S> Debug for TreeFeedback<'_, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TreeFeedback {{}}")
+    }
+}
+
+impl<'a, S> TreeFeedback<'a, S> {
+    /// Create a new [`TreeFeedback`]
+    #[must_use]
+    pub fn new(context: &'a TreeContext) -> Self {
+        Self {
+            ctx: &context,
+            novelties: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, S> Named for TreeFeedback<'a, S> {
+    fn name(&self) -> &str {
+        "TreeFeedback"
+    }
+}
+
+impl<'a, S> Feedback<S> for TreeFeedback<'a, S>
+where
+    S: HasMetadata + HasCorpus<Input = TestTree> + State<Input = TestTree>,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        input: &TestTree,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        self.novelties = None;
+        if *exit_kind != ExitKind::Ok {
+            return Ok(false);
+        }
+        if std::str::from_utf8(input.bytes()).is_err() {
+            return Ok(false);
+        }
+        let meta = state
+            .metadata_map()
+            .get::<TreeMetaData>()
+            .expect("TreeMeta not in the state");
+        let novelties = meta.syntax.novelties(input.node_index(self.ctx.language));
+        if novelties.is_empty() {
+            return Ok(false);
+        }
+        let covered = meta.syntax.len() + novelties.len();
+        self.novelties = Some(novelties);
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: "syntax".to_string(),
+                value: UserStats::new(UserStatsValue::Number(covered as u64), AggregatorOps::Avg),
+                phantom: PhantomData,
+            },
+        )?;
+        Ok(true)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        state.corpus().load_input_into(testcase)?;
+        let input = testcase.input().as_ref().unwrap().clone();
+        let meta = state
+            .metadata_map_mut()
+            .get_mut::<TreeMetaData>()
+            .expect("TreeMeta not in the state");
+        if let Some(novelties) = self.novelties.take() {
+            meta.syntax.extend(novelties);
+        }
+        if let Some(dir) = self.ctx.branches_dir() {
+            publish_tree(dir, input.bytes())?;
+        }
+        meta.add_tree(&input, self.ctx);
+        Ok(())
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &TestTree) -> Result<(), Error> {
+        self.novelties = None;
+        Ok(())
+    }
+}
+
+/// Write `bytes` to `dir` under its hash so that other clients can pick it up.
+fn publish_tree(dir: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let name = format!("{:016x}", libafl_bolts::hash_std(bytes));
+    let path = dir.join(&name);
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    // Readers skip dot files, so they never see a half-written tree.
+    let tmp = dir.join(format!(".{name}.tmp"));
+    fs::write(&tmp, bytes)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+pub struct TreeSpliceMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeSpliceMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeSpliceMutator"
+    }
+}
+
+impl<'a> TreeSpliceMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self {
+            ctx
+        }
+    }
+}
+
+/// An input, with its parse tree and [`NodeIndex`] kept until its bytes change.
+///
+/// Mutators, feedbacks and `TreeMetaData` get the tree from here, so an input is parsed
+/// once however many of them look at it. Changing the bytes through
+/// [`HasBytesVec::bytes_mut`] drops both.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct TestTree {
+    bytes: Vec<u8>,
+    #[serde(skip)]
+    tree: OnceCell<Tree>,
+    #[serde(skip)]
+    index: OnceCell<NodeIndex>,
+}
+
+impl TestTree {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            ..Self::default()
+        }
+    }
+
+    /// An input whose tree is already at hand, so it is not parsed again.
+    pub fn with_tree(bytes: Vec<u8>, tree: Tree) -> Self {
+        Self {
+            bytes,
+            tree: OnceCell::from(tree),
+            ..Self::default()
+        }
+    }
+
+    /// The tree of the input in `language`, the language of every input of a fuzzer.
+    pub fn tree(&self, language: Language) -> &Tree {
+        self.tree
+            .get_or_init(|| parse_bytes(language, &self.bytes, None))
+    }
+
+    pub fn node_index(&self, language: Language) -> &NodeIndex {
+        self.index
+            .get_or_init(|| NodeIndex::new(self.tree(language)))
+    }
+}
+
+/// The nodes of a tree in preorder, for walks that do not need tree-sitter's `Node`s.
+#[derive(Clone, Debug, Default)]
+pub struct NodeIndex {
+    kinds: Vec<u16>,
+    /// The index of the parent, `None` for the root.
+    parents: Vec<Option<usize>>,
+    /// The field the node is in its parent, if any.
+    fields: Vec<Option<u16>>,
+    /// Error or missing nodes.
+    broken: Vec<bool>,
+}
+
+impl NodeIndex {
+    fn new(tree: &Tree) -> Self {
+        let mut index = Self::default();
+        let mut cursor = tree.walk();
+        let mut parents = Vec::new();
+        loop {
+            let node = cursor.node();
+            index.kinds.push(node.kind_id());
+            index.parents.push(parents.last().copied());
+            index.fields.push(cursor.field_id());
+            index.broken.push(node.is_error() || node.is_missing());
+            if cursor.goto_first_child() {
+                parents.push(index.kinds.len() - 1);
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return index;
+                }
+                parents.pop();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+}
+
+impl Input for TestTree {
+    /// Named by content, so that a resumed queue maps every input to its old file.
+    fn generate_name(&self, _idx: usize) -> String {
+        format!("{:016x}", libafl_bolts::hash_std(&self.bytes))
+    }
+
+    /// Raw, so that crashes can be replayed and triaged.
+    fn to_file<P>(&self, path: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        write_file_atomic(path, &self.bytes)
+    }
+
+    fn from_file<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = File::open(path)?;
+        let mut bytes: Vec<u8> = vec![];
+        file.read_to_end(&mut bytes)?;
+        Ok(Self::new(bytes))
+    }
+}
+
+impl HasLen for TestTree {
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl HasTargetBytes for TestTree {
+    fn target_bytes(&self) -> OwnedSlice<'_, u8> {
+        OwnedSlice::from(&self.bytes)
+    }
+}
+
+impl HasBytesVec for TestTree {
+    fn bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+
+    /// The bytes to change, which is why the tree is dropped.
+    fn bytes_mut(&mut self) -> &mut Vec<u8> {
+        self.tree.take();
+        self.index.take();
+        &mut self.bytes
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeSpliceMutator<'_> 
+where
+    S: HasCorpus<Input = TestTree> + HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        stage_idx: i32,
+    ) -> Result<libafl::prelude::MutationResult, libafl::prelude::Error> {
+        let mut tmp = None;
+        if std::str::from_utf8(input.bytes()).is_ok() {
+            let tree = input.tree(self.ctx.language).clone();
+            tmp = with_meta(state, |meta, rng| {
+                meta.splice_tree(input.bytes(), tree, self.ctx, rng)
+            });
+        }
+        match tmp {
+            Some(spliced) if !spliced.bytes().is_empty() => {
+                *input = spliced;
+                Ok(MutationResult::Mutated)
+            }
+            _ => Ok(MutationResult::Skipped),
+        }
+    }
+}
+
+/// Nests a node into itself, like Nautilus' `NautilusRecursionMutator`.
+///
+/// Picks a node with a descendant that it could stand in for, and repeats the text
+/// between the two: `[[1]]` becomes `[[[[[1]]]]]`. Deep nesting is where recursive
+/// descent parsers run out of stack.
+pub struct TreeRecursionMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeRecursionMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeRecursionMutator"
+    }
+}
+
+impl<'a> TreeRecursionMutator<'a> {
+    /// Nesting is doubled up to this many times.
+    const MAX_DOUBLINGS: u32 = 12;
+
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+
+    /// Pairs of a node and a descendant whose place it can take.
+    fn recursions<'t>(&self, tree: &'t Tree) -> Vec<(Node<'t>, Node<'t>)> {
+        let mut pairs = Vec::new();
+        let mut nodes = vec![tree.root_node()];
+        while let Some(node) = nodes.pop() {
+            let mut cursor = node.walk();
+            nodes.extend(node.named_children(&mut cursor));
+            let kinds = self.ctx.node_types.compatible_kinds(&node);
+            let mut ancestor = node.parent();
+            while let Some(outer) = ancestor {
+                // Skip wrappers that add nothing to repeat
+                if kinds.contains(outer.kind()) && outer.byte_range() != node.byte_range() {
+                    pairs.push((outer, node));
+                }
+                ancestor = outer.parent();
+            }
+        }
+        pairs
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeRecursionMutator<'_>
+where
+    S: HasMaxSize + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        if std::str::from_utf8(input.bytes()).is_err() {
+            return Ok(MutationResult::Skipped);
+        }
+        let pairs = self.recursions(input.tree(self.ctx.language));
+        let mut rng = StateRng(state.rand_mut());
+        let Some((outer, inner)) = pairs.choose(&mut rng).copied() else {
+            return Ok(MutationResult::Skipped);
+        };
+        let text = input.bytes();
+        let prefix = &text[outer.start_byte()..inner.start_byte()];
+        let suffix = &text[inner.end_byte()..outer.end_byte()];
+        let doublings = rng.gen_range(1..=Self::MAX_DOUBLINGS);
+        let room = state.max_size().saturating_sub(text.len()) / (prefix.len() + suffix.len());
+        let times = (1usize << doublings).min(room);
+        if times == 0 {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let mut nested = Vec::with_capacity(text.len() + times * (prefix.len() + suffix.len()));
+        nested.extend_from_slice(&text[..outer.start_byte()]);
+        for _ in 0..times {
+            nested.extend_from_slice(prefix);
+        }
+        nested.extend_from_slice(&text[outer.start_byte()..outer.end_byte()]);
+        for _ in 0..times {
+            nested.extend_from_slice(suffix);
+        }
+        nested.extend_from_slice(&text[outer.end_byte()..]);
+        *input.bytes_mut() = nested;
+        Ok(MutationResult::Mutated)
+    }
+}
+
+fn disjoint(a: &Node, b: &Node) -> bool {
+    a.end_byte() <= b.start_byte() || b.end_byte() <= a.start_byte()
+}
+
+/// Apply `edits` to `text`, the source of `tree`.
+fn render(tree: &Tree, text: &[u8], edits: &Edits) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(text.len());
+    tree_sitter_edit::render(&mut result, tree, text, edits).ok()?;
+    Some(result)
+}
+
+/// Parse `input`, let `edit` pick edits to it with the tree, `TreeMetaData` and the RNG of
+/// `state`, and apply them. The tree is edited and reparsed along with the text, so the
+/// next mutation does not parse the input from scratch.
+pub(crate) fn mutate_tree<S>(
+    ctx: &TreeContext,
+    state: &mut S,
+    input: &mut TestTree,
+    edit: impl FnOnce(&mut TreeMetaData, &mut StateRng<'_, S::Rand>, &[u8], &Tree) -> Option<Edits>,
+) -> Result<MutationResult, Error>
+where
+    S: HasMetadata + HasRand,
+{
+    if std::str::from_utf8(input.bytes()).is_err() {
+        return Ok(MutationResult::Skipped);
+    }
+    let tree = input.tree(ctx.language);
+    let Some(edits) = with_meta(state, |meta, rng| edit(meta, rng, input.bytes(), tree)) else {
+        return Ok(MutationResult::Skipped);
+    };
+    match render(tree, input.bytes(), &edits) {
+        Some(text) if text != input.bytes() => {
+            let mut tree = tree.clone();
+            edit_tree(&mut tree, &edits);
+            let tree = parse_bytes(ctx.language, &text, Some(&tree));
+            *input = TestTree::with_tree(text, tree);
+            Ok(MutationResult::Mutated)
+        }
+        _ => Ok(MutationResult::Skipped),
+    }
+}
+
+/// Swaps two subtrees of an input that can take each other's place.
+///
+/// Unlike splicing from `Branches`, the input keeps its own identifiers, so that they
+/// still refer to what they are declared as.
+pub struct TreeSwapMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeSwapMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeSwapMutator"
+    }
+}
+
+impl<'a> TreeSwapMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+
+    fn swap(
+        &self,
+        meta: &TreeMetaData,
+        rng: &mut impl Rng,
+        text: &[u8],
+        tree: &Tree,
+    ) -> Option<Edits> {
+        let ctx = self.ctx;
+        let nodes = meta.all_nodes(tree);
+        for _ in 0..16 {
+            let a = *nodes.choose(rng)?;
+            let kinds = ctx.node_types.compatible_kinds(&a);
+            let mut others: Vec<_> = nodes
+                .iter()
+                .filter(|b| kinds.contains(b.kind()) && disjoint(&a, b))
+                .filter(|b| text[a.byte_range()] != text[b.byte_range()])
+                .collect();
+            others.shuffle(rng);
+            // The other way around as well
+            if let Some(b) = others
+                .into_iter()
+                .find(|b| ctx.node_types.compatible_kinds(b).contains(a.kind()))
+            {
+                let mut edits = Edits::default();
+                edits.0.insert(a.id(), text[b.byte_range()].to_vec());
+                edits.0.insert(b.id(), text[a.byte_range()].to_vec());
+                return Some(edits);
+            }
+        }
+        None
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeSwapMutator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            self.swap(meta, rng, text, tree)
+        })
+    }
+}
+
+/// Repeats a child of a list in place, a statement or an array element, say.
+pub struct TreeDuplicateMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeDuplicateMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeDuplicateMutator"
+    }
+}
+
+impl<'a> TreeDuplicateMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+
+    fn duplicate(
+        &self,
+        meta: &TreeMetaData,
+        rng: &mut impl Rng,
+        text: &[u8],
+        tree: &Tree,
+    ) -> Option<Edits> {
+        let lists: Vec<_> = meta
+            .lists(tree)
+            .into_iter()
+            .filter(|(_, _, children)| !children.is_empty())
+            .collect();
+        let (list, _, children) = lists.choose(rng)?;
+        let child = *children.choose(rng)?;
+        let separator = meta.separator(text, list, children, rng)?;
+        let mut edits = Edits::default();
+        let replace =
+            TreeMetaData::next_to(text, &child, &separator, &text[child.byte_range()], false);
+        edits.0.insert(child.id(), replace);
+        Some(edits)
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeDuplicateMutator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            self.duplicate(meta, rng, text, tree)
+        })
+    }
+}
+
+/// Moves a child of a list into another list that takes it, an item between blocks, say.
+pub struct TreeMoveMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeMoveMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeMoveMutator"
+    }
+}
+
+impl<'a> TreeMoveMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+
+    fn move_node(
+        &self,
+        meta: &TreeMetaData,
+        rng: &mut impl Rng,
+        text: &[u8],
+        tree: &Tree,
+    ) -> Option<Edits> {
+        let lists = meta.lists(tree);
+        let sources: Vec<_> = lists.iter().filter(|(_, _, c)| !c.is_empty()).collect();
+        for _ in 0..16 {
+            let (from, _, children) = *sources.choose(rng)?;
+            let child = *children.choose(rng)?;
+            let targets: Vec<_> = lists
+                .iter()
+                .filter(|(to, kinds, _)| to.id() != from.id() && kinds.contains(child.kind()))
+                .collect();
+            let Some((to, _, to_children)) = targets.choose(rng) else {
+                continue;
+            };
+            // Whatever gets edited for the insertion must not contain the child
+            let anchors: Vec<_> = to_children.iter().filter(|a| disjoint(a, &child)).collect();
+            let child_text = &text[child.byte_range()];
+            let mut edits = Edits::default();
+            let anchor = anchors.choose(rng).copied();
+            if let Some(anchor) = anchor {
+                let Some(separator) = meta.separator(text, to, to_children, rng) else {
+                    continue;
+                };
+                let before = rng.gen_bool(0.5);
+                let replace = TreeMetaData::next_to(text, anchor, &separator, child_text, before);
+                edits.0.insert(anchor.id(), replace);
+            } else if to_children.is_empty() && disjoint(to, &child) {
+                edits
+                    .0
+                    .insert(to.id(), TreeMetaData::into_empty(text, to, child_text));
+            } else {
+                continue;
+            }
+            edits.0.insert(child.id(), Vec::new());
+            // Along with the token between it and another child, lest `[1, 2]` become `[, 2]`
+            let is_child =
+                |n: Option<Node>| n.is_some_and(|n| children.iter().any(|c| c.id() == n.id()));
+            let separator = child
+                .next_sibling()
+                .filter(|s| !s.is_named() && is_child(s.next_sibling()))
+                .or_else(|| {
+                    child
+                        .prev_sibling()
+                        .filter(|s| !s.is_named() && is_child(s.prev_sibling()))
+                });
+            if let Some(separator) = separator {
+                edits.0.insert(separator.id(), Vec::new());
+            }
+            return Some(edits);
+        }
+        None
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeMoveMutator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            self.move_node(meta, rng, text, tree)
+        })
+    }
+}
+
+/// Replaces subtrees of an input with compatible ones of another testcase in the corpus.
+///
+/// Like the splice of Nautilus' `NautilusSpliceMutator`, and unlike `Branches`, this takes
+/// subtrees that were in one input together, so larger structures of two good inputs combine.
+pub struct TreeCrossoverMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeCrossoverMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeCrossoverMutator"
+    }
+}
+
+impl<'a> TreeCrossoverMutator<'a> {
+    /// Subtrees replaced at once, at most.
+    const MAX_EXCHANGES: usize = 3;
+
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+
+    fn crossover(
+        &self,
+        meta: &TreeMetaData,
+        rng: &mut impl Rng,
+        text: &[u8],
+        tree: &Tree,
+        other: &TestTree,
+    ) -> Option<Edits> {
+        let ctx = self.ctx;
+        std::str::from_utf8(other.bytes()).ok()?;
+        let other_tree = other.tree(ctx.language);
+        let mut donors: HashMap<&str, Vec<Node>> = HashMap::new();
+        for node in meta.all_nodes(other_tree) {
+            donors.entry(node.kind()).or_default().push(node);
+        }
+        let mut nodes = meta.all_nodes(tree);
+        nodes.shuffle(rng);
+        let exchanges = rng.gen_range(1..=Self::MAX_EXCHANGES);
+        let mut replaced: Vec<Node> = Vec::with_capacity(exchanges);
+        let mut edits = Edits::default();
+        // Each try looks up the slot of the node, a few dozen are plenty
+        for node in nodes.into_iter().take(64) {
+            if replaced.iter().any(|r| !disjoint(r, &node)) {
+                continue;
+            }
+            let node_text = &text[node.byte_range()];
+            let candidates: Vec<_> = ctx
+                .node_types
+                .compatible_kinds(&node)
+                .iter()
+                .filter_map(|kind| donors.get(kind.as_str()))
+                .flatten()
+                .filter(|d| &other.bytes()[d.byte_range()] != node_text)
+                .collect();
+            let Some(donor) = candidates.choose(rng).copied() else {
+                continue;
+            };
+            edits
+                .0
+                .insert(node.id(), other.bytes()[donor.byte_range()].to_vec());
+            replaced.push(node);
+            if replaced.len() == exchanges {
+                break;
+            }
+        }
+        (!replaced.is_empty()).then_some(edits)
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeCrossoverMutator<'_>
+where
+    S: HasCorpus<Input = TestTree> + HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        if state.corpus().count() == 0 {
+            return Ok(MutationResult::Skipped);
+        }
+        // Crossing an input with itself is what `TreeSwapMutator` is for
+        let idx = random_corpus_id!(state.corpus(), state.rand_mut());
+        if state.corpus().current().is_some_and(|cur| cur == idx) {
+            return Ok(MutationResult::Skipped);
+        }
+        let other = {
+            let mut testcase = state.corpus().get(idx)?.borrow_mut();
+            testcase.load_input(state.corpus())?.clone()
+        };
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            self.crossover(meta, rng, text, tree, &other)
+        })
+    }
+}
+
+/// Deletes a node, or replaces it with a smaller one of its kind, like a deletion of
+/// `TreeSpliceMutator` on its own.
+pub struct TreeDeleteMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeDeleteMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeDeleteMutator"
+    }
+}
+
+impl<'a> TreeDeleteMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeDeleteMutator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            let (id, bytes, _) = meta.delete_node(text, tree, self.ctx, rng);
+            let mut edits = Edits::default();
+            edits.0.insert(id, bytes);
+            Some(edits)
+        })
+    }
+}
+
+/// Adds a sibling to a list, like an insertion of `TreeSpliceMutator` on its own.
+pub struct TreeInsertMutator<'a> {
+    ctx: &'a TreeContext,
+}
+
+impl Named for TreeInsertMutator<'_> {
+    fn name(&self) -> &str {
+        "TreeInsertMutator"
+    }
+}
+
+impl<'a> TreeInsertMutator<'a> {
+    pub fn new(ctx: &'a TreeContext) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeInsertMutator<'_>
+where
+    S: HasMetadata + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        mutate_tree(self.ctx, state, input, |meta, rng, text, tree| {
+            let (id, bytes, _) = meta.insert_node(text, tree, rng)?;
+            let mut edits = Edits::default();
+            edits.0.insert(id, bytes);
+            Some(edits)
+        })
+    }
+}
+
+/// LibAFL's byte-level havoc on the text of an input, as a single tree mutation.
+///
+/// Few of its inputs parse, but the parser of the target has to reject them somewhere.
+pub struct TreeHavocMutator<S>
+where
+    S: HasCorpus<Input = TestTree> + HasMaxSize + HasRand,
+{
+    havoc: StdScheduledMutator<TestTree, HavocMutationsType, S>,
+}
+
+impl<S> Named for TreeHavocMutator<S>
+where
+    S: HasCorpus<Input = TestTree> + HasMaxSize + HasRand,
+{
+    fn name(&self) -> &str {
+        "TreeHavocMutator"
+    }
+}
+
+impl<S> TreeHavocMutator<S>
+where
+    S: HasCorpus<Input = TestTree> + HasMaxSize + HasRand,
+{
+    pub fn new() -> Self {
+        Self {
+            havoc: StdScheduledMutator::new(havoc_mutations()),
+        }
+    }
+}
+
+impl<S> Default for TreeHavocMutator<S>
+where
+    S: HasCorpus<Input = TestTree> + HasMaxSize + HasRand,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Mutator<TestTree, S> for TreeHavocMutator<S>
+where
+    S: HasCorpus<Input = TestTree> + HasMaxSize + HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut TestTree,
+        stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        self.havoc.mutate(state, input, stage_idx)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TreeMetaData {
+    node_types: NodeTypes,
+    // rng: StdRng,
+    branches: Branches,
+    kinds: (Vec<String>, HashSet<String>),
+    /// What goes between repeated children, by the kind of their parent.
+    separators: HashMap<String, Vec<Vec<u8>>>,
+    syntax: SyntaxCoverage,
+}
+
+libafl_bolts::impl_serdeany!(TreeMetaData);
+
+impl TreeMetaData {
+    pub(crate) fn add_tree(&mut self, txt: &TestTree, ctx: &TreeContext) {
+        if std::str::from_utf8(txt.bytes()).is_ok() {
+            let tree = txt.tree(ctx.language);
+            self.add_separators(txt.bytes(), tree);
+            self.branches.add_tree(txt.bytes(), tree);
+            self.branches.0.keys()
+                .for_each(|s| {
+                    let contains = self.kinds.1.contains(s);
+                    if !contains {
+                        self.kinds.0.push(s.to_owned());
+                        self.kinds.1.insert(s.to_owned());
+                    }
+                }); 
+        }
+    }
+
+   pub fn new( 
+          ctx: &TreeContext,
+          files: HashMap<String, (Vec<u8>, Tree)>
+          ) -> Self {
+
+        // By path, the order of a `HashMap` differs from run to run
+        let mut files: Vec<_> = files.into_iter().collect();
+        files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        let mut separators = HashMap::new();
+        for (_, (txt, tree)) in &files {
+            Self::find_separators(&ctx.node_types, &mut separators, txt, tree);
+        }
+        let branches = Branches::new(
+            files
+                .into_iter()
+                .map(|(_, (txt, tree))| (txt, tree))
+                .collect(),
+        );
+
+        let kinds = (
+            branches.0.keys()
+            .map(|s| 
+                 s.clone()
+                 ).collect(),
+            branches.0.keys()
+            .map(|s| 
+                 s.clone()
+                 ).collect()
+                 );
+        Self {
+            node_types: ctx.node_types.clone(),
+            // language,
+            // rng: rand::rngs::StdRng::seed_from_u64(11),
+            branches,
+            kinds,
+            separators,
+            syntax: SyntaxCoverage::default(),
+        }
+   } 
+
+    /// Parse every file in `corpus_dirs` and collect the initial chunks.
+    ///
+    /// Dot files are skipped, a queue keeps its metadata and lock files there.
+    pub fn load(ctx: &TreeContext, corpus_dirs: &[PathBuf]) -> Result<Self, Error> {
+        let mut files = HashMap::new();
+        for path in crate::replay::expand_inputs(corpus_dirs)? {
+            if let Ok(s) = fs::read_to_string(&path) {
+                // println!("Parsing tree {path:?}");
+                let tree = parse(ctx.language, &s);
+                files.insert(String::from(path.to_string_lossy()), (s.into_bytes(), tree));
+            }
+        }
+        println!("Loading initial chunks: {}", files.len());
+        Ok(Self::new(ctx, files))
+    }
+
+    /// The known fragments of `kind`.
+    pub(crate) fn fragments(&self, kind: &str) -> &[Vec<u8>] {
+        self.branches.0.get(kind).map_or(&[], |s| s.0.as_slice())
+    }
+
+    /// Remember a fragment that did not come from a parsed input.
+    fn add_fragment(&mut self, kind: &str, fragment: Vec<u8>) {
+        let slot = self
+            .branches
+            .0
+            .entry(kind.to_owned())
+            .or_insert_with(|| (vec![], HashSet::with_capacity(1)));
+        if slot.1.insert(fragment.clone()) {
+            slot.0.push(fragment);
+        }
+        if self.kinds.1.insert(kind.to_owned()) {
+            self.kinds.0.push(kind.to_owned());
+        }
+    }
+
+    /// Collect the text between adjacent repeated children in `tree`, see [`NodeTypes::list_types`].
+    fn find_separators(
+        node_types: &NodeTypes,
+        separators: &mut HashMap<String, Vec<Vec<u8>>>,
+        text: &[u8],
+        tree: &Tree,
+    ) {
+        let mut nodes = vec![tree.root_node()];
+        while let Some(node) = nodes.pop() {
+            let kinds = node_types.list_kinds(&node);
+            let mut cursor = node.walk();
+            let children: Vec<_> = node.named_children(&mut cursor).collect();
+            for pair in children.windows(2) {
+                if !kinds.contains(pair[0].kind()) || !kinds.contains(pair[1].kind()) {
+                    continue;
+                }
+                let separator = text[pair[0].end_byte()..pair[1].start_byte()].to_vec();
+                let known = separators.entry(node.kind().to_owned()).or_default();
+                // A handful is all the variety there is
+                if known.len() < 8 && !known.contains(&separator) {
+                    known.push(separator);
+                }
+            }
+            nodes.extend(children);
+        }
+    }
+
+    fn add_separators(&mut self, text: &[u8], tree: &Tree) {
+        Self::find_separators(&self.node_types, &mut self.separators, text, tree);
+    }
+
+    /// The shortest known fragment of `kind`.
+    pub(crate) fn smallest_fragment(&self, kind: &str) -> Option<&[u8]> {
+        self.branches
+            .0
+            .get(kind)
+            .and_then(|s| s.0.iter().min_by_key(|f| f.len()))
+            .map(Vec::as_slice)
+    }
+
+    fn pick_usize(&mut self, n: usize, rng: &mut impl Rng) -> usize {
+        rng.gen_range(0..n)
+    }
+
+    fn pick_idx<T>(&mut self, v: &Vec<T>, rng: &mut impl Rng) -> usize {
+        self.pick_usize(v.len(), rng)
+    }
+
+    pub(crate) fn all_nodes<'b>(&self, tree: &'b Tree) -> Vec<Node<'b>> {
+        let mut all = Vec::with_capacity(16); // min
+        let root = tree.root_node();
+        let mut cursor = tree.walk();
+        let mut nodes: Vec<_> = root.children(&mut cursor).collect();
+        while !nodes.is_empty() {
+            let mut next = Vec::new();
+            for node in nodes {
+                all.push(node);
+                let mut child_cursor = tree.walk();
+                for child in node.children(&mut child_cursor) {
+                    debug_assert!(child.id() != node.id());
+                    next.push(child);
+                }
+            }
+
+            nodes = next;
+        }
+        all
+    }
+
+    fn pick_node<'b>(&mut self, tree: &'b Tree, rng: &mut impl Rng) -> Node<'b> {
+        let nodes = self.all_nodes(tree);
+        if nodes.is_empty() {
+            return tree.root_node();
+        }
+        *nodes.get(self.pick_idx(&nodes, rng)).unwrap()
+    }
+
+    fn delete_node(
+        &mut self,
+        _text: &[u8],
+        tree: &Tree,
+        ctx: &TreeContext,
+        rng: &mut impl Rng,
+    ) -> (usize, Vec<u8>, isize) {
+        let chaotic = rng.gen_range(0..100) < ctx.config.chaos;
+        if chaotic {
+            let node = self.pick_node(tree, rng);
+            return (node.id(), Vec::new(), Self::delta(node, &[]));
+        }
+        let nodes = self.all_nodes(tree);
+        if nodes.iter().all(|n| !self.node_types.optional_node(n)) {
+            let node = self.pick_node(tree, rng);
+            return (node.id(), Vec::new(), Self::delta(node, &[]));
+        }
+        let mut node = nodes.get(self.pick_idx(&nodes, rng)).unwrap();
+        while !self.node_types.optional_node(node) {
+            // dbg!("Delete");
+            node = nodes.get(self.pick_idx(&nodes, rng)).unwrap();
+        }
+        (node.id(), Vec::new(), Self::delta(*node, &[]))
+    }
+
+    fn splice_node(
+        &mut self,
+        text: &[u8],
+        tree: &Tree,
+        ctx: &TreeContext,
+        rng: &mut impl Rng,
+    ) -> (usize, Vec<u8>, isize) {
+        // Nothing to pick a random kind from with an empty corpus
+        let mut chaotic = rng.gen_range(0..100) < ctx.config.chaos && !self.kinds.0.is_empty();
+        let mut generated = false;
+
+        let mut node = tree.root_node();
+        let mut candidates = Vec::new();
+        // When modified trees are re-parsed, their nodes may have novel kinds
+        // not in Branches (candidates.len() == 0). Also, avoid not mutating
+        // (candidates.len() == 1).
+        let mut loop_times = 0;
+        while candidates.len() <= 1 {
+            loop_times += 1;
+            if loop_times >= 100 && !self.kinds.0.is_empty() {
+                chaotic = true;
+            }
+            if loop_times >= 200 {
+                // No kind has two fragments yet, leave the input as it is
+                return (node.id(), text[node.byte_range()].to_vec(), 0);
+            }
+            // dbg!("candidates");
+            node = self.pick_node(tree, rng);
+            candidates = if chaotic {
+                let kind_idx = rng.gen_range(0..self.kinds.0.len());
+                let kind = self.kinds.0.get(kind_idx).unwrap();
+                self.branches.0.get(kind)
+                    .map(|s| &s.0)
+                    .unwrap().clone()
+            } else {
+                // Whatever the slot of the node takes fits, e.g. any `_expression`
+                self.node_types
+                    .compatible_kinds(&node)
+                    .iter()
+                    .filter_map(|kind| self.branches.0.get(kind))
+                    .flat_map(|s| s.0.iter().cloned())
+                    .collect()
+            };
+            // Nothing to swap in for this kind, make something up (once, it has to parse)
+            if !chaotic && candidates.len() <= 1 && !generated {
+                generated = true;
+                if let Some(replace) =
+                    SubtreeGenerator::new(ctx, self, rng).fitting(text, tree, &node)
+                {
+                    self.add_fragment(node.kind(), replace.clone());
+                    let delta = Self::delta(node, replace.as_slice());
+                    return (node.id(), replace, delta);
+                }
+            }
+        }
+
+        let idx = rng.gen_range(0..candidates.len());
+        let mut candidate = candidates.get(idx).unwrap();
+        // Try to avoid not mutating
+        let node_text = &text[node.byte_range()];
+        while candidates.len() > 1 && candidate == &node_text {
+            // dbg!("candidates");
+            let idx = rng.gen_range(0..candidates.len());
+            candidate = candidates.get(idx).unwrap();
+        }
+        // eprintln!(
+        //     "Replacing '{}' with '{}'",
+        //     std::str::from_utf8(&text[node.byte_range()]).unwrap(),
+        //     std::str::from_utf8(candidate).unwrap(),
+        // );
+        let replace = candidate.to_vec();
+        let delta = Self::delta(node, replace.as_slice());
+        (node.id(), replace, delta)
+    }
+
+    /// Add siblings next to a child of a node that takes a list of them, like an array.
+    ///
+    /// The new siblings come from `Branches`, and the separator from the children of
+    /// another node of the same kind. A list without children gets them in front of its
+    /// closing token.
+    fn insert_node(
+        &mut self,
+        text: &[u8],
+        tree: &Tree,
+        rng: &mut impl Rng,
+    ) -> Option<(usize, Vec<u8>, isize)> {
+        let mut lists: Vec<_> = self
+            .all_nodes(tree)
+            .into_iter()
+            .chain([tree.root_node()])
+            .filter(|n| self.separators.contains_key(n.kind()))
+            .collect();
+        lists.shuffle(rng);
+        for list in lists {
+            let kinds = self.node_types.list_kinds(&list);
+            let fragments: Vec<&Vec<u8>> = kinds
+                .iter()
+                .flat_map(|k| self.branches.0.get(k).map(|s| &s.0).into_iter().flatten())
+                .collect();
+            if fragments.is_empty() {
+                continue;
+            }
+            let separators = &self.separators[list.kind()];
+            let separator = separators.choose(rng).unwrap();
+            let count = rng.gen_range(1..=3);
+            let mut siblings = Vec::new();
+            for i in 0..count {
+                if i > 0 {
+                    siblings.extend_from_slice(separator);
+                }
+                siblings.extend_from_slice(fragments.choose(rng).unwrap());
+            }
+
+            let mut cursor = list.walk();
+            let children: Vec<_> = list
+                .named_children(&mut cursor)
+                .filter(|c| kinds.contains(c.kind()))
+                .collect();
+            let child = children.choose(rng).copied();
+            let (node, replace) = if let Some(child) = child {
+                let before = rng.gen_bool(0.5);
+                (
+                    child,
+                    Self::next_to(text, &child, separator, &siblings, before),
+                )
+            } else {
+                (list, Self::into_empty(text, &list, &siblings))
+            };
+            let delta = Self::delta(node, replace.as_slice());
+            return Some((node.id(), replace, delta));
+        }
+        None
+    }
+
+    /// The text of `child` with `siblings` and `separator` before or after it.
+    fn next_to(
+        text: &[u8],
+        child: &Node,
+        separator: &[u8],
+        siblings: &[u8],
+        before: bool,
+    ) -> Vec<u8> {
+        let child_text = &text[child.byte_range()];
+        let mut replace = Vec::with_capacity(child_text.len() + separator.len() + siblings.len());
+        if before {
+            replace.extend_from_slice(siblings);
+            replace.extend_from_slice(separator);
+            replace.extend_from_slice(child_text);
+        } else {
+            replace.extend_from_slice(child_text);
+            replace.extend_from_slice(separator);
+            replace.extend_from_slice(siblings);
+        }
+        replace
+    }
+
+    /// The text of a `list` without children with `siblings` in front of its closing token.
+    fn into_empty(text: &[u8], list: &Node, siblings: &[u8]) -> Vec<u8> {
+        let range = list.byte_range();
+        let at = match list.child(list.child_count().saturating_sub(1)) {
+            Some(last) if !last.is_named() => last.start_byte(),
+            _ => range.end,
+        };
+        let mut replace = text[range.start..at].to_vec();
+        replace.extend_from_slice(siblings);
+        replace.extend_from_slice(&text[at..range.end]);
+        replace
+    }
+
+    /// The nodes of `tree` that take a list of children, with the children they have.
+    fn lists<'b>(&self, tree: &'b Tree) -> Vec<(Node<'b>, BTreeSet<String>, Vec<Node<'b>>)> {
+        self.all_nodes(tree)
+            .into_iter()
+            .chain([tree.root_node()])
+            .filter_map(|list| {
+                let kinds = self.node_types.list_kinds(&list);
+                if kinds.is_empty() {
+                    return None;
+                }
+                let mut cursor = list.walk();
+                let children = list
+                    .named_children(&mut cursor)
+                    .filter(|c| kinds.contains(c.kind()))
+                    .collect();
+                Some((list, kinds, children))
+            })
+            .collect()
+    }
+
+    /// What goes between the `children` of `list`: what is already there, else what is
+    /// between the children of another node of its kind.
+    fn separator(
+        &self,
+        text: &[u8],
+        list: &Node,
+        children: &[Node],
+        rng: &mut impl Rng,
+    ) -> Option<Vec<u8>> {
+        if let [first, second, ..] = children {
+            return Some(text[first.end_byte()..second.start_byte()].to_vec());
+        }
+        self.separators
+            .get(list.kind())
+            .and_then(|s| s.choose(rng))
+            .cloned()
+    }
+
+    fn delta(node: Node<'_>, replace: &[u8]) -> isize {
+        let range = node.byte_range();
+        isize::try_from(replace.len()).unwrap_or_default()
+            - isize::try_from(range.end - range.start).unwrap_or_default()
+    }
+
+    pub fn splice_tree(
+        &mut self,
+        text0: &[u8],
+        mut tree: Tree,
+        ctx: &TreeContext,
+        rng: &mut impl Rng,
+    ) -> Option<TestTree> {
+        // TODO: Assert that text0 and tree.root_node() are the same length?
+        let config = &ctx.config;
+        let mut edits = Edits::default();
+        if config.inter_splices < 2 {
+            return None;
+        }
+        let splices = rng.gen_range(1..config.inter_splices);
+        let mut text = Vec::from(text0);
+        let mut sz = isize::try_from(text.len()).unwrap_or_default();
+        for i in 0..splices {
+            let roll = rng.gen_range(0..100);
+            let (id, bytes, delta) = if roll < config.deletions {
+                self.delete_node(text.as_slice(), &tree, ctx, rng)
+            } else if roll < config.deletions + config.insertions {
+                match self.insert_node(text.as_slice(), &tree, rng) {
+                    Some(insertion) => insertion,
+                    None => self.splice_node(text.as_slice(), &tree, ctx, rng),
+                }
+            } else {
+                self.splice_node(text.as_slice(), &tree, ctx, rng)
+            };
+            sz += delta;
+            let sized_out = usize::try_from(sz).unwrap_or_default() >= config.max_size;
+            edits.0.insert(id, bytes);
+            let last = i + 1 == splices || sized_out;
+            if last || config.reparse.is_some_and(|n| (i + 1) % n == 0) {
+                text = render(&tree, text.as_slice(), &edits)?;
+                edit_tree(&mut tree, &edits);
+                tree = parse_bytes(ctx.language, text.as_slice(), Some(&tree));
+                if last {
+                    break;
+                }
+                edits = Edits::default();
+            }
+        }
+        Some(TestTree::with_tree(text, tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn end_point_of_insertions() {
+        let start = Point::new(2, 4);
+        assert_eq!(end_point(start, b""), start);
+        assert_eq!(end_point(start, b"abc"), Point::new(2, 7));
+        assert_eq!(end_point(start, b"ab\nc"), Point::new(3, 1));
+        assert_eq!(end_point(start, b"a\n\nbc\n"), Point::new(5, 0));
+    }
+
+    /// The first node, depth first, that `matches`.
+    fn find<'t>(tree: &'t Tree, matches: impl Fn(Node) -> bool) -> Node<'t> {
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if matches(node) {
+                return node;
+            }
+            stack.extend((0..node.child_count()).rev().filter_map(|i| node.child(i)));
+        }
+        panic!("No such node in {}", tree.root_node().to_sexp());
+    }
+
+    /// Apply `edits`, pairs of the text of a node and its replacement, to `code`: the
+    /// node of `marker` after them must end up where it is in the rendered text.
+    fn assert_moves(code: &str, edits: &[(&str, &str)], marker: &str) {
+        let language = tree_sitter_json::language();
+        let mut tree = parse(language, code);
+        let mut all = Edits::default();
+        for (old, new) in edits {
+            let node = find(&tree, |node| &code[node.byte_range()] == *old);
+            all.0.insert(node.id(), new.as_bytes().to_vec());
+        }
+        let id = find(&tree, |node| &code[node.byte_range()] == marker).id();
+        let text = render(&tree, code.as_bytes(), &all).unwrap();
+
+        edit_tree(&mut tree, &all);
+        let moved = find(&tree, |node| node.id() == id);
+        let fresh = parse_bytes(language, &text, None);
+        let expected = find(&fresh, |node| &text[node.byte_range()] == marker.as_bytes());
+        assert_eq!(moved.byte_range(), expected.byte_range());
+        assert_eq!(moved.start_position(), expected.start_position());
+        assert_eq!(moved.end_position(), expected.end_position());
+
+        let incremental = parse_bytes(language, &text, Some(&tree));
+        assert_eq!(
+            incremental.root_node().to_sexp(),
+            fresh.root_node().to_sexp()
+        );
+    }
+
+    #[test]
+    fn edit_tree_on_one_line() {
+        assert_moves(r#"{"a": [1, 2], "z": 3}"#, &[("1", "100")], r#""z""#);
+    }
+
+    #[test]
+    fn edit_tree_across_lines() {
+        let code = "{\n  \"a\": [1, 2],\n  \"b\": 3, \"z\": 4\n}\n";
+        assert_moves(code, &[("2", "[\n5,\n\n6]"), ("3", "7")], r#""z""#);
+        assert_moves(
+            code,
+            &[("[1, 2]", "8"), ("\"b\": 3", "\"c\":\n9")],
+            r#""z""#,
+        );
+    }
+
+    #[test]
+    fn edit_tree_skips_nodes_below_edits() {
+        let code = "[[1,\n2], \"z\"]";
+        assert_moves(code, &[("[1,\n2]", "{}"), ("2", "\n\n22")], r#""z""#);
+    }
+
+    #[test]
+    fn splice_tree_caches_the_tree() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let ctx = TreeContext::new(tree_sitter_json::language(), tree_sitter_json::NODE_TYPES);
+        let code = br#"{"a": [1, {"b": null}], "c": "d"}"#;
+        let tree = parse_bytes(ctx.language, code, None);
+        let files = HashMap::from([("a.json".to_string(), (code.to_vec(), tree.clone()))]);
+        let mut meta = TreeMetaData::new(&ctx, files);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut spliced = 0;
+        for _ in 0..32 {
+            let Some(input) = meta.splice_tree(code, tree.clone(), &ctx, &mut rng) else {
+                continue;
+            };
+            let cached = input.tree.get().expect("No tree with the splice");
+            let fresh = parse_bytes(ctx.language, input.bytes(), None);
+            assert_eq!(cached.root_node().to_sexp(), fresh.root_node().to_sexp());
+            assert_eq!(
+                cached.root_node().byte_range(),
+                fresh.root_node().byte_range()
+            );
+            spliced += 1;
+        }
+        assert!(spliced > 0);
+    }
+}
@@ -0,0 +1,320 @@
+//! Group the crashes of a campaign by where they happen.
+//!
+//! Each input runs in a child process of the triage binary with `RUST_BACKTRACE=1`.
+//! The panic message and the backtrace (or the sanitizer stack trace after an abort)
+//! are read back from its stderr, and inputs whose top frames match land in one bucket.
+//! Hangs are killed after `TREE_FUZZER_TIMEOUT` milliseconds, see [`crate::replay`].
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    env, fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use crate::replay::{expand_inputs, run_with_timeout, timeout, Outcome};
+
+/// Set in the child that runs a single input.
+const CHILD_ENV: &str = "TREE_FUZZER_TRIAGE_CHILD";
+
+/// Number of frames of the target that make up a bucket.
+const BUCKET_FRAMES: usize = 5;
+
+/// Frames of the panic machinery and the standard library, the same for every crash.
+const IGNORED_FRAMES: &[&str] = &[
+    "__rustc::",
+    "rust_begin_unwind",
+    "rust_panic",
+    "std::",
+    "core::",
+    "alloc::",
+    "__libc_start",
+    "__asan",
+    "__sanitizer",
+    "tree_fuzzer::replay::",
+    "tree_fuzzer::triage::",
+];
+
+/// Frames of the runtime matched whole, `main_fuzz` of a target is worth keeping.
+const IGNORED_SYMBOLS: &[&str] = &["_start", "main"];
+
+/// A crashing input and what it left on stderr.
+#[derive(Clone, Debug)]
+pub struct Crash {
+    pub path: PathBuf,
+    pub size: u64,
+    pub outcome: Outcome,
+    /// The panic location and message, or the last line of stderr after an abort.
+    pub message: String,
+    /// Frames of the target, innermost first, normalized.
+    pub frames: Vec<String>,
+}
+
+impl Crash {
+    /// Run `path` in a child process and parse its stderr.
+    pub fn reproduce(path: &Path) -> io::Result<Self> {
+        let timeout = timeout();
+        let (outcome, stderr) = run_with_timeout(
+            Command::new(env::current_exe()?)
+                .arg(path)
+                .env(CHILD_ENV, "1")
+                .env("RUST_BACKTRACE", "1")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+            timeout,
+        )?;
+        let size = fs::metadata(path)?.len();
+        Ok(Self::from_run(path, size, outcome, &stderr, timeout))
+    }
+
+    /// Parse the `stderr` of a run of `path` that ended with `outcome`, with `RUST_BACKTRACE=1`.
+    pub fn from_run(
+        path: &Path,
+        size: u64,
+        outcome: Outcome,
+        stderr: &[u8],
+        timeout: Duration,
+    ) -> Self {
+        let stderr = String::from_utf8_lossy(stderr);
+        let message = if outcome == Outcome::Timeout {
+            // Whatever the target printed last says little about where it got stuck
+            format!("no result after {} ms", timeout.as_millis())
+        } else {
+            panic_message(&stderr)
+                .or_else(|| {
+                    stderr
+                        .lines()
+                        .rev()
+                        .find(|l| !l.trim().is_empty())
+                        .map(str::to_owned)
+                })
+                .unwrap_or_default()
+        };
+        Self {
+            path: path.to_owned(),
+            size,
+            outcome,
+            message,
+            frames: stack_frames(&stderr),
+        }
+    }
+
+    /// Hash of the outcome and the top frames, or the message if there is no stack.
+    pub fn bucket(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match &self.outcome {
+            Outcome::Abort { signal, .. } => signal.hash(&mut hasher),
+            outcome => outcome.to_string().hash(&mut hasher),
+        }
+        if self.frames.is_empty() {
+            self.message.hash(&mut hasher);
+        } else {
+            self.frames
+                .iter()
+                .take(BUCKET_FRAMES)
+                .for_each(|f| f.hash(&mut hasher));
+        }
+        hasher.finish()
+    }
+}
+
+/// `panicked at <location>:` and the message below it.
+fn panic_message(stderr: &str) -> Option<String> {
+    let mut lines = stderr.lines();
+    let location = lines.find_map(|l| l.split_once(" panicked at ").map(|(_, loc)| loc))?;
+    let message: Vec<_> = lines
+        .take_while(|l| !l.starts_with("stack backtrace:") && !l.starts_with("note:"))
+        .collect();
+    Some(format!(
+        "{} {}",
+        location.trim_end_matches(':'),
+        message.join(" ").trim()
+    ))
+}
+
+/// Function names of a Rust backtrace (`  3: name`) or a sanitizer report (`#3 0x... in name`).
+///
+/// Only lines after `stack backtrace:` or the `==ERROR:` header count, whatever the
+/// target printed before (inputs, say) could look like frames too.
+fn stack_frames(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .skip_while(|line| !line.starts_with("stack backtrace:") && !line.contains("==ERROR:"))
+        .filter_map(|line| {
+            let line = line.trim();
+            if let Some((idx, name)) = line.split_once(": ") {
+                idx.parse::<usize>().ok().map(|_| name)
+            } else if line.starts_with('#') {
+                line.split_once(" in ")
+                    .map(|(_, rest)| rest.split_whitespace().next().unwrap_or(rest))
+            } else {
+                None
+            }
+        })
+        .map(normalize_frame)
+        .filter(|f| !is_ignored(f))
+        .collect()
+}
+
+/// Also covers trait impls of the standard library, `<T as core::ops::Index>::index`.
+fn is_ignored(frame: &str) -> bool {
+    let frame = frame.trim_start_matches('<');
+    IGNORED_FRAMES.iter().any(|i| frame.starts_with(i))
+        || IGNORED_SYMBOLS.contains(&frame)
+        || ["std::", "core::", "alloc::"]
+            .iter()
+            .any(|krate| frame.contains(&format!(" as {krate}")))
+}
+
+/// Strip the symbol hash, which changes with every rebuild.
+fn normalize_frame(frame: &str) -> String {
+    match frame.rsplit_once("::h") {
+        Some((name, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            name.to_owned()
+        }
+        _ => frame.to_owned(),
+    }
+}
+
+/// Entry point of a triage binary, `target` is the fuzzed function.
+///
+/// Takes crash directories or files as arguments, `./crashes` and `./hangs` if there
+/// are none, and returns the exit code.
+pub fn main(target: fn(&[u8])) -> i32 {
+    if env::var_os(CHILD_ENV).is_some() {
+        let path = env::args_os()
+            .nth(1)
+            .expect("No input given to the triage child");
+        let bytes = fs::read(&path).expect("Failed to read the input");
+        target(&bytes);
+        return 0;
+    }
+    let mut paths: Vec<PathBuf> = env::args_os().skip(1).map(PathBuf::from).collect();
+    if paths.is_empty() {
+        paths = ["./crashes", "./hangs"]
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|p| p.exists())
+            .collect();
+    }
+    match triage(&paths) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Failed to triage {paths:?}: {err}");
+            1
+        }
+    }
+}
+
+/// Reproduce every input in `paths` and print the buckets.
+pub fn triage(paths: &[PathBuf]) -> io::Result<()> {
+    let files = expand_inputs(paths)?;
+    println!("Triaging {} inputs", files.len());
+    let mut buckets: BTreeMap<u64, Vec<Crash>> = BTreeMap::new();
+    let mut passed = Vec::new();
+    for file in files {
+        let crash = Crash::reproduce(&file)?;
+        println!("{}: {}", file.display(), crash.outcome);
+        if crash.outcome.is_pass() {
+            passed.push(file);
+        } else {
+            buckets.entry(crash.bucket()).or_default().push(crash);
+        }
+    }
+
+    let mut buckets: Vec<_> = buckets.into_iter().collect();
+    buckets.sort_by_key(|(_, crashes)| std::cmp::Reverse(crashes.len()));
+    println!();
+    println!(
+        "{:<16}  {:>5}  {:<20}  message",
+        "bucket", "count", "outcome"
+    );
+    for (bucket, crashes) in &buckets {
+        // The smallest input is the easiest to look at
+        let repr = crashes.iter().min_by_key(|c| c.size).unwrap();
+        println!(
+            "{bucket:016x}  {:>5}  {:<20}  {}",
+            crashes.len(),
+            repr.outcome.to_string(),
+            repr.message
+        );
+    }
+    println!();
+    for (bucket, crashes) in &buckets {
+        let repr = crashes.iter().min_by_key(|c| c.size).unwrap();
+        println!(
+            "{bucket:016x}: {} ({} bytes)",
+            repr.path.display(),
+            repr.size
+        );
+        for frame in repr.frames.iter().take(BUCKET_FRAMES) {
+            println!("    {frame}");
+        }
+    }
+    if !passed.is_empty() {
+        println!();
+        println!("{} inputs no longer crash:", passed.len());
+        for path in passed {
+            println!("    {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_of_a_panic() {
+        let stderr = "\
+0: printed by the target
+thread 'main' panicked at src/lib.rs:5:9:
+index out of bounds
+stack backtrace:
+   0: rust_begin_unwind
+             at /rustc/library/std/src/panicking.rs:645:5
+   1: core::panicking::panic_bounds_check
+   2: json::parse::value::h0123456789abcdef
+             at src/parse.rs:10:5
+   3: main_fuzz
+   4: main
+note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+";
+        assert_eq!(stack_frames(stderr), ["json::parse::value", "main_fuzz"]);
+        assert_eq!(
+            panic_message(stderr).unwrap(),
+            "src/lib.rs:5:9 index out of bounds"
+        );
+    }
+
+    #[test]
+    fn frames_of_a_sanitizer_report() {
+        let stderr = "\
+#1 0x1 in printed_by_the_target
+==42==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000011
+    #0 0x55d1 in parse_value /src/parse.c:10:5
+    #1 0x55d2 in __libc_start_main
+    #2 0x55d3 in _start
+";
+        assert_eq!(stack_frames(stderr), ["parse_value"]);
+    }
+
+    #[test]
+    fn no_frames_without_a_header() {
+        assert!(stack_frames("0: foo\n#1 0x1 in bar\n").is_empty());
+    }
+
+    #[test]
+    fn trait_impls_of_the_standard_library_are_ignored() {
+        assert!(is_ignored(
+            "<alloc::vec::Vec<T> as core::ops::Index<I>>::index"
+        ));
+        assert!(is_ignored("<json::Value as core::fmt::Display>::fmt"));
+        assert!(!is_ignored("json::Value::fmt"));
+    }
+}